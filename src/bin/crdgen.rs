@@ -3,7 +3,7 @@
 //! Generates YAML manifests for Berg operator CRDs.
 //! Run with: cargo run --bin crdgen
 
-use berg_operator::crds::{Challenge, ChallengeInstance, ChallengeInstanceClass};
+use berg_operator::crds::{Challenge, ChallengeInstance, ChallengeInstanceClass, Event, WarmPool};
 use kube::CustomResourceExt;
 
 fn main() {
@@ -28,4 +28,15 @@ fn main() {
     let class_crd = serde_yaml::to_string(&ChallengeInstanceClass::crd())
         .expect("Failed to serialize ChallengeInstanceClass CRD");
     println!("{}", class_crd.trim());
+    println!("---");
+
+    // Event CRD
+    let event_crd = serde_yaml::to_string(&Event::crd()).expect("Failed to serialize Event CRD");
+    println!("{}", event_crd.trim());
+    println!("---");
+
+    // WarmPool CRD
+    let pool_crd =
+        serde_yaml::to_string(&WarmPool::crd()).expect("Failed to serialize WarmPool CRD");
+    println!("{}", pool_crd.trim());
 }
@@ -1,6 +1,31 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
 use std::env;
 
+/// How namespaces of challenge instances are named
+#[derive(Clone, Debug, PartialEq)]
+pub enum NamespaceNaming {
+    /// "<prefix>-<challenge>-<ownerId>", one namespace per owner and challenge
+    Owner,
+    /// "<prefix>-<challenge>-<hash>", hashed over owner and instance ID so an owner can run
+    /// several instances of a challenge at once
+    Hashed,
+}
+
+impl std::str::FromStr for NamespaceNaming {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "owner" => Ok(Self::Owner),
+            "hashed" => Ok(Self::Hashed),
+            other => Err(Error::ConfigError(format!(
+                "Unknown namespace naming {}, expected owner or hashed",
+                other
+            ))),
+        }
+    }
+}
+
 // TODO: use config crate here
 #[derive(Clone, Debug)]
 pub struct ControllerConfig {
@@ -12,6 +37,18 @@ pub struct ControllerConfig {
 
     /// Namespace prefix for challenge instance namespaces
     pub namespace_prefix: String,
+
+    /// Naming scheme for challenge instance namespaces
+    pub namespace_naming: NamespaceNaming,
+
+    /// ChallengeInstance labels starting with this prefix are copied to instance namespaces
+    pub label_passthrough_prefix: Option<String>,
+
+    /// Namespace flag secrets may be read from, besides the namespace of the challenge
+    pub flag_secret_namespace: Option<String>,
+
+    /// Key of the flag hashes in instance statuses and pod templates
+    pub flag_hash_key: Vec<u8>,
 }
 
 impl ControllerConfig {
@@ -21,6 +58,21 @@ impl ControllerConfig {
                 .unwrap_or_else(|_| "default".to_string()),
             default_timeout: env::var("DEFAULT_TIMEOUT").unwrap_or_else(|_| "2h".to_string()),
             namespace_prefix: env::var("NAMESPACE_PREFIX").unwrap_or_else(|_| "ci".to_string()),
+            namespace_naming: env::var("NAMESPACE_NAMING")
+                .as_deref()
+                .unwrap_or("hashed")
+                .parse()?,
+            label_passthrough_prefix: env::var("LABEL_PASSTHROUGH_PREFIX")
+                .ok()
+                .filter(|prefix| !prefix.is_empty()),
+            flag_secret_namespace: env::var("FLAG_SECRET_NAMESPACE")
+                .ok()
+                .filter(|namespace| !namespace.is_empty()),
+            flag_hash_key: env::var("FLAG_HASH_KEY")
+                .ok()
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| Error::ConfigError("FLAG_HASH_KEY must be set".to_string()))?
+                .into_bytes(),
         })
     }
 }
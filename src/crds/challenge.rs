@@ -3,6 +3,8 @@ use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::crds::PodSecurityContextConfig;
+
 /// Schema for Kubernetes probe objects (liveness/readiness)
 fn probe_schema(_gen: &mut SchemaGenerator) -> Schema {
     serde_json::from_value(serde_json::json!({
@@ -38,6 +40,8 @@ pub struct ChallengeSpec {
     pub author: String,
     pub description: String,
     pub flag: String,
+    /// Expected shape of instance flags, e.g. "flag{...}" where "..." matches anything
+    /// Prefix with "regex:" to match against a regular expression instead
     pub flag_format: Option<String>,
     pub dynamic_flag_mode: Option<DynamicFlagMode>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -48,9 +52,17 @@ pub struct ChallengeSpec {
     pub categories: Vec<String>,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Name of the Event bounding instances of this challenge
+    /// Names without a matching Event resource impose no restrictions
     pub event: Option<String>,
     #[serde(default)]
     pub allow_outbound_traffic: bool,
+    /// Hosts reachable even if outbound traffic is not allowed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_egress: Vec<EgressSpec>,
+    /// Whether every owner gets their own instance or all owners share one
+    #[serde(default)]
+    pub instancing: Instancing,
     #[serde(default)]
     pub containers: Vec<ContainerSpec>,
     #[serde(default)]
@@ -64,11 +76,58 @@ pub enum DynamicFlagMode {
     Leetify,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Instancing {
+    /// One namespace per ChallengeInstance
+    #[default]
+    Dedicated,
+    /// One instance per challenge and class, serving every ChallengeInstance bound to it
+    Shared,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EgressSpec {
+    /// Exact name or pattern with * wildcards (e.g., "api.github.com" or "*.github.com")
+    pub fqdn: String,
+    /// Ports that may be reached, all of them if empty
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ContainerSpec {
     pub hostname: String,
     pub image: String,
+    /// Pod the container runs in, containers of the same pod reach each other on localhost
+    /// Defaults to a pod of its own named after the hostname
+    /// Other pods still reach each container by its hostname, names and ports must not collide
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"))]
+    pub pod: Option<String>,
+    /// Share the process namespace between the containers of the pod
+    #[serde(default)]
+    pub share_process_namespace: bool,
+    /// Entrypoint override, the image entrypoint is used if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    /// Arguments to the entrypoint, the image cmd is used if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Working directory override
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    /// Steps run to completion before the container starts, e.g. to seed a database
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub init_containers: Vec<InitContainerSpec>,
+    /// Auxiliary containers, e.g. admin bots, are never exposed and have restricted egress
+    #[serde(default)]
+    pub role: ContainerRole,
+    /// Scratch and persistent volumes mounted into the container and its init containers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<VolumeSpec>,
     #[serde(default)]
     pub environment: HashMap<String, String>,
     #[serde(default)]
@@ -78,17 +137,115 @@ pub struct ContainerSpec {
     pub resource_limits: Option<ResourceSpec>,
     #[serde(default)]
     pub additional_capabilities: Vec<String>,
+    /// Containers this one may connect to inside the instance, all of them if unset
+    /// An empty list isolates the container from the rest of the instance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_peers: Option<Vec<PeerSpec>>,
     pub runtime_class_name: Option<String>,
+    /// Overrides of the pod security context set by the instance class
+    /// User, group and seccomp overrides only apply to this container, fsGroup and
+    /// supplementalGroups apply to its pod and must match the other containers in it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security_context: Option<PodSecurityContextConfig>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[schemars(schema_with = "probe_schema")]
     pub readiness_probe: Option<serde_json::Value>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[schemars(schema_with = "probe_schema")]
     pub liveness_probe: Option<serde_json::Value>,
+    /// Egress bandwidth limit of the pod, overriding the class default (e.g., "10M")
+    #[schemars(regex(pattern = crate::utils::QUANTITY_PATTERN))]
     pub egress_bandwidth: Option<String>,
+    /// Ingress bandwidth limit of the pod, overriding the class default (e.g., "10M")
+    #[schemars(regex(pattern = crate::utils::QUANTITY_PATTERN))]
     pub ingress_bandwidth: Option<String>,
 }
 
+impl ContainerSpec {
+    /// Name of the pod the container runs in
+    pub fn pod_name(&self) -> &str {
+        self.pod.as_deref().unwrap_or(&self.hostname)
+    }
+
+    /// Whether the container is hidden from players
+    pub fn is_auxiliary(&self) -> bool {
+        self.role == ContainerRole::Auxiliary
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRole {
+    /// Player-facing container, exposed through its ports
+    #[default]
+    Challenge,
+    /// Helper such as an admin bot, only able to reach the instance itself
+    #[serde(alias = "bot")]
+    Auxiliary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerSpec {
+    /// Hostname of the peer container, instances of challenges naming an unknown one fail
+    pub hostname: String,
+    /// Ports of the peer that may be reached, all of them if empty
+    #[serde(default)]
+    pub ports: Vec<u16>,
+}
+
+/// Init container sharing the environment and flag mounts of its container
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InitContainerSpec {
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"))]
+    pub name: String,
+    /// Image to run, defaults to the image of the container
+    pub image: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    pub working_dir: Option<String>,
+    /// Additional environment, overriding the environment of the container
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeSpec {
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"))]
+    pub name: String,
+    pub mount_path: String,
+    /// Scratch space living as long as the pod, the default if no source is set
+    pub empty_dir: Option<EmptyDirVolume>,
+    /// Claim living as long as the instance, takes precedence over emptyDir
+    /// Its data is kept across resets, the pod is replaced with the Recreate strategy
+    pub persistent: Option<PersistentVolume>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmptyDirVolume {
+    /// Backing medium, e.g. Memory for a tmpfs or a larger /dev/shm
+    pub medium: Option<StorageMedium>,
+    /// Size limit (e.g., "64Mi"), counted against the memory limit for Memory
+    pub size_limit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq)]
+pub enum StorageMedium {
+    Memory,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistentVolume {
+    /// Requested size (e.g., "1Gi")
+    pub size: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PortSpec {
@@ -114,25 +271,60 @@ pub enum PortType {
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct DynamicFlag {
     pub env: Option<EnvFlag>,
+    /// Cookie handed to a bot, e.g. for XSS challenges
+    pub cookie: Option<CookieFlag>,
     pub content: Option<ContentFlag>,
     pub executable: Option<ExecutableFlag>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct EnvFlag {
     pub name: String,
+    /// Named instance flag to use, defaults to the instance flag
+    pub flag_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CookieFlag {
+    /// Name of the cookie
+    pub name: String,
+    /// Domain the cookie is set for, e.g. the hostname of the challenge container
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    #[serde(default)]
+    pub http_only: bool,
+    /// Named instance flag to use, defaults to the instance flag
+    pub flag_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ContentFlag {
     pub path: String,
     pub mode: Option<u32>,
+    /// Named instance flag to use, defaults to the instance flag
+    pub flag_name: Option<String>,
+    /// Mount the flag's parent directory instead of a single file (no subPath)
+    /// The kubelet then refreshes the file in place when the flag is rotated
+    /// The mount replaces the whole directory, so the flag needs a dedicated one (e.g. /flag)
+    #[serde(default)]
+    pub mount_directory: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct ExecutableFlag {
     pub path: String,
     pub mode: Option<u32>,
+    /// Named instance flag to use, defaults to the instance flag
+    pub flag_name: Option<String>,
+    /// Mount the flag's parent directory instead of a single file (no subPath)
+    /// The kubelet then refreshes the file in place when the flag is rotated
+    /// The mount replaces the whole directory, so the flag needs a dedicated one (e.g. /flag)
+    #[serde(default)]
+    pub mount_directory: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
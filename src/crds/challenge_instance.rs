@@ -1,6 +1,7 @@
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use crate::date_time::DateTime;
 
@@ -38,9 +39,22 @@ pub struct ChallengeInstanceSpec {
     pub owner_id: String,
 
     /// Pre-generated flag for this instance
+    /// Leave empty when the flag is provided through flagSecretRef
+    #[serde(default)]
     #[schemars(length(max = 1024))]
     pub flag: String,
 
+    /// Secret key holding the flag for this instance
+    /// Takes precedence over flag and keeps the flag out of the ChallengeInstance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flag_secret_ref: Option<SecretKeyRef>,
+
+    /// Additional named flags for multi-stage challenges (e.g. "root")
+    /// Containers select them through the flagName of their dynamic flag
+    /// Names must be DNS labels of at most 47 characters, as they end up in volume names
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub flags: BTreeMap<String, String>,
+
     /// ChallengeInstanceClass to use for this instance
     /// If not specified, the default class will be used
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -53,6 +67,16 @@ pub struct ChallengeInstanceSpec {
 
     /// Reason for termination
     pub termination_reason: Option<TerminationReason>,
+
+    /// Increment to reset the instance: all workloads are restarted with fresh flag volumes
+    /// while the namespace, endpoints and persistent volume data are kept
+    #[serde(default)]
+    #[schemars(range(min = 0))]
+    pub reset_generation: i64,
+
+    /// Scale the instance down and pause its expiry timer until unset
+    #[serde(default)]
+    pub suspended: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -62,11 +86,24 @@ pub struct ChallengeRef {
     pub namespace: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretKeyRef {
+    /// Name of the Secret
+    pub name: String,
+    /// Namespace of the Secret, defaults to the configured flag secret namespace or the
+    /// namespace of the challenge, which are also the only namespaces allowed
+    pub namespace: Option<String>,
+    /// Key within the Secret data
+    pub key: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq)]
 pub enum TerminationReason {
     UserRequest,
     Timeout,
     AdminTermination,
+    EventEnded,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
@@ -91,10 +128,40 @@ pub struct ChallengeInstanceStatus {
     pub terminated_at: Option<DateTime>,
     pub expires_at: Option<DateTime>,
 
+    /// Lifetime left when the instance was suspended, in seconds
+    pub remaining_seconds: Option<i64>,
+
+    /// Event bounding the lifetime of this instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+
+    /// Flag paths with {entropy} resolved, keyed by "<container>/<content|executable>"
+    /// Meant for authors and admins only, platforms must not show these to players
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resolved_flag_paths: BTreeMap<String, String>,
+
+    /// Shared instance this instance is bound to, for challenges with shared instancing
+    /// Bound instances have no namespace of their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_instance: Option<String>,
+
+    /// HMAC-SHA-256 of the flag currently deployed, keyed by the controller, used to detect
+    /// flag rotation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flag_hash: Option<String>,
+
     /// Status conditions
     #[serde(default)]
     pub conditions: Vec<Condition>,
 
+    /// Last handled spec.resetGeneration
+    #[serde(default)]
+    pub observed_reset_generation: i64,
+
+    /// Number of times the instance has been reset
+    #[serde(default)]
+    pub reset_count: i64,
+
     /// Last observed generation
     pub observed_generation: Option<i64>,
 }
@@ -105,6 +172,7 @@ pub enum Phase {
     Creating,
     Starting,
     Running,
+    Suspended,
     Terminating,
     Terminated,
     Failed,
@@ -1,6 +1,7 @@
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// ChallengeInstanceClass defines configuration for ChallengeInstances
 /// Similar to StorageClass in Kubernetes, this allows different "tiers" of instances
@@ -23,6 +24,22 @@ pub struct ChallengeInstanceClassSpec {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_resources: Option<ResourceDefaults>,
 
+    /// ResourceQuota applied to the namespace of every instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaConfig>,
+
+    /// LimitRange applied to the namespace of every instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_range: Option<LimitRangeConfig>,
+
+    /// Labels, annotations and Pod Security Admission levels of instance namespaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace_metadata: Option<NamespaceMetadataConfig>,
+
+    /// Storage of persistent challenge volumes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageConfig>,
+
     /// Network configuration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub network: Option<NetworkConfig>,
@@ -42,6 +59,10 @@ pub struct ChallengeInstanceClassSpec {
     /// Default timeout for instances using this class
     #[serde(skip_serializing_if = "Option::is_none")]
     pub default_timeout: Option<String>,
+
+    /// Allow instances of challenges that are still hidden (e.g. for author testing)
+    #[serde(default)]
+    pub ignore_hide_until: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -89,6 +110,117 @@ pub struct ResourceDefaults {
     /// Default memory limit (e.g., "512Mi")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub memory_limit: Option<String>,
+
+    /// Default ephemeral storage request (e.g., "64Mi"), only set when the quota bounds
+    /// ephemeral storage and the LimitRange has no default for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ephemeral_storage_request: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaConfig {
+    /// Total CPU limit of all pods in the namespace (e.g., "2")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<String>,
+
+    /// Total memory limit of all pods in the namespace (e.g., "2Gi")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+
+    /// Maximum number of pods in the namespace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0))]
+    pub pods: Option<i32>,
+
+    /// Maximum number of services in the namespace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0))]
+    pub services: Option<i32>,
+
+    /// Total ephemeral storage requested by all pods in the namespace (e.g., "4Gi")
+    /// Containers get the default ephemeral storage request so their pods are still admitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ephemeral_storage: Option<String>,
+}
+
+/// Per-container constraints, keyed by resource name (e.g., "cpu", "memory")
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitRangeConfig {
+    /// Limits of containers that don't set their own
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub default: BTreeMap<String, String>,
+
+    /// Requests of containers that don't set their own
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub default_request: BTreeMap<String, String>,
+
+    /// Highest limit a container may set
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max: BTreeMap<String, String>,
+
+    /// Lowest request a container may set
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub min: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceMetadataConfig {
+    /// Pod Security Admission levels enforced on instance namespaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_security: Option<PodSecurityConfig>,
+
+    /// Extra labels of instance namespaces, berg labels take precedence
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// Extra annotations of instance namespaces
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PodSecurityConfig {
+    /// Level rejecting violating pods (pod-security.kubernetes.io/enforce)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enforce: Option<PodSecurityLevel>,
+
+    /// Level returning warnings to clients (pod-security.kubernetes.io/warn)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn: Option<PodSecurityLevel>,
+
+    /// Level recorded in the audit log (pod-security.kubernetes.io/audit)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit: Option<PodSecurityLevel>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PodSecurityLevel {
+    Privileged,
+    Baseline,
+    Restricted,
+}
+
+impl PodSecurityLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PodSecurityLevel::Privileged => "privileged",
+            PodSecurityLevel::Baseline => "baseline",
+            PodSecurityLevel::Restricted => "restricted",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageConfig {
+    /// StorageClass of instance PVCs, the cluster default if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_class_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -96,15 +228,41 @@ pub struct ResourceDefaults {
 pub struct NetworkConfig {
     /// Default egress bandwidth limit (e.g., "10M")
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(regex(pattern = crate::utils::QUANTITY_PATTERN))]
     pub egress_bandwidth: Option<String>,
 
     /// Default ingress bandwidth limit (e.g., "10M")
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(regex(pattern = crate::utils::QUANTITY_PATTERN))]
     pub ingress_bandwidth: Option<String>,
 
     /// Enable additional headless service for containers
     #[serde(default)]
     pub additional_headless_service: bool,
+
+    /// Implementation of the network policies isolating instances
+    #[serde(default)]
+    pub policy_backend: NetworkPolicyBackend,
+
+    /// Address ranges of the nodes (e.g., "10.0.0.0/24"), used by the kubernetes backend
+    /// to limit gateway traffic to the nodes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub node_cidrs: Vec<String>,
+
+    /// Address ranges of the pods (e.g., "10.244.0.0/16"), used by the kubernetes backend
+    /// to keep other instances out of NodePort and outbound traffic
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pod_cidrs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkPolicyBackend {
+    /// CiliumNetworkPolicy, supporting DNS and FQDN rules
+    #[default]
+    Cilium,
+    /// networking.k8s.io/v1 NetworkPolicy, for clusters without Cilium
+    Kubernetes,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
@@ -127,15 +285,44 @@ pub struct SecurityConfig {
     /// Pod security context settings
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pod_security_context: Option<PodSecurityContextConfig>,
+
+    /// Capabilities challenges may request through additionalCapabilities
+    /// If not specified, any capability may be requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_capabilities: Option<Vec<String>>,
+
+    /// Capabilities dropped from every container (e.g., ["ALL"])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drop_capabilities: Vec<String>,
+
+    /// Capabilities added to every container, e.g. to re-add basics after dropping ALL
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub default_capabilities: Vec<String>,
+
+    /// Mount the root filesystem of challenge containers read-only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_only_root_filesystem: Option<bool>,
+
+    /// Allow processes to gain more privileges than their parent (defaults to true)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_privilege_escalation: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct PodSecurityContextConfig {
     /// Run as non-root user
     #[serde(skip_serializing_if = "Option::is_none")]
     pub run_as_non_root: Option<bool>,
 
+    /// UID to run the container processes as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_as_user: Option<i64>,
+
+    /// GID to run the container processes as
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_as_group: Option<i64>,
+
     /// FS group for volumes
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fs_group: Option<i64>,
@@ -143,6 +330,48 @@ pub struct PodSecurityContextConfig {
     /// Supplemental groups
     #[serde(skip_serializing_if = "Option::is_none")]
     pub supplemental_groups: Option<Vec<i64>>,
+
+    /// Seccomp profile of the pod
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp_profile: Option<SeccompProfileConfig>,
+}
+
+impl PodSecurityContextConfig {
+    /// Layer another config on top of this one, fields set in the override win
+    pub fn merge(&self, over: &PodSecurityContextConfig) -> PodSecurityContextConfig {
+        PodSecurityContextConfig {
+            run_as_non_root: over.run_as_non_root.or(self.run_as_non_root),
+            run_as_user: over.run_as_user.or(self.run_as_user),
+            run_as_group: over.run_as_group.or(self.run_as_group),
+            fs_group: over.fs_group.or(self.fs_group),
+            supplemental_groups: over
+                .supplemental_groups
+                .clone()
+                .or_else(|| self.supplemental_groups.clone()),
+            seccomp_profile: over
+                .seccomp_profile
+                .clone()
+                .or_else(|| self.seccomp_profile.clone()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompProfileConfig {
+    /// Kind of seccomp profile
+    pub r#type: SeccompProfileType,
+
+    /// Profile file relative to the kubelet seccomp directory, only for Localhost
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub localhost_profile: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq)]
+pub enum SeccompProfileType {
+    RuntimeDefault,
+    Unconfined,
+    Localhost,
 }
 
 fn default_http_port() -> u16 {
@@ -16,9 +16,22 @@ pub struct CiliumNetworkPolicySpec {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub endpoint_selector: Option<LabelSelector>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingress: Option<Vec<CiliumIngressRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub egress: Option<Vec<CiliumEgressRule>>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CiliumIngressRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_endpoints: Option<Vec<LabelSelector>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_entities: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_ports: Option<Vec<CiliumPortRule>>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CiliumEgressRule {
@@ -26,7 +39,7 @@ pub struct CiliumEgressRule {
     pub to_endpoints: Option<Vec<LabelSelector>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub to_entities: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "toFQDNs", skip_serializing_if = "Option::is_none")]
     pub to_fqd_ns: Option<Vec<CiliumFQDNRule>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub to_ports: Option<Vec<CiliumPortRule>>,
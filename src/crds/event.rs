@@ -0,0 +1,37 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::date_time::DateTime;
+
+/// Event groups challenges into a time window, e.g. a CTF
+/// Challenges reference it through ChallengeSpec.event and their instances are bounded by it
+#[derive(CustomResource, Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "berg.norelect.ch",
+    version = "v1",
+    kind = "Event",
+    plural = "events",
+    singular = "event",
+    shortname = "bergevent",
+    namespaced = false,
+    printcolumn = r#"{"name":"Starts", "type":"date", "jsonPath":".spec.startsAt"}"#,
+    printcolumn = r#"{"name":"Freezes", "type":"date", "jsonPath":".spec.freezesAt"}"#,
+    printcolumn = r#"{"name":"Ends", "type":"date", "jsonPath":".spec.endsAt"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSpec {
+    /// Human readable event name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    /// No instances can be created before this time
+    pub starts_at: DateTime,
+
+    /// All instances are terminated at this time
+    pub ends_at: DateTime,
+
+    /// No new instances can be created after this time, running instances continue
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freezes_at: Option<DateTime>,
+}
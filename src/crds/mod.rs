@@ -2,26 +2,34 @@ pub mod challenge;
 pub mod challenge_instance;
 pub mod challenge_instance_class;
 pub mod cilium;
+pub mod event;
 pub mod gateway;
+pub mod warm_pool;
 
 // Re-export types
 pub use challenge::{
-    Challenge, ChallengeSpec, ContainerSpec, ContentFlag, DynamicFlag, DynamicFlagMode, EnvFlag,
-    ExecutableFlag, PortSpec, PortType, ResourceSpec,
+    Challenge, ChallengeSpec, ContainerRole, ContainerSpec, ContentFlag, CookieFlag, DynamicFlag,
+    DynamicFlagMode, EgressSpec, EmptyDirVolume, EnvFlag, ExecutableFlag, InitContainerSpec,
+    Instancing, PeerSpec, PersistentVolume, PortSpec, PortType, ResourceSpec, StorageMedium,
+    VolumeSpec,
 };
 pub use challenge_instance::{
     ChallengeInstance, ChallengeInstanceSpec, ChallengeInstanceStatus, ChallengeRef, Condition,
-    ConditionStatus, Phase, ServiceEndpoint, TerminationReason,
+    ConditionStatus, Phase, SecretKeyRef, ServiceEndpoint, TerminationReason,
 };
 pub use challenge_instance_class::{
     ChallengeInstanceClass, ChallengeInstanceClassSpec, GatewayConfig, ImagePullConfig,
-    NetworkConfig, ResourceDefaults, SecurityConfig,
+    LimitRangeConfig, NamespaceMetadataConfig, NetworkConfig, NetworkPolicyBackend,
+    PodSecurityConfig, PodSecurityContextConfig, PodSecurityLevel, QuotaConfig, ResourceDefaults,
+    SeccompProfileConfig, SeccompProfileType, SecurityConfig, StorageConfig,
 };
 pub use cilium::{
-    CiliumDnsRule, CiliumEgressRule, CiliumFQDNRule, CiliumL7Rule, CiliumNetworkPolicy,
-    CiliumNetworkPolicySpec, CiliumPortProtocol, CiliumPortRule,
+    CiliumDnsRule, CiliumEgressRule, CiliumFQDNRule, CiliumIngressRule, CiliumL7Rule,
+    CiliumNetworkPolicy, CiliumNetworkPolicySpec, CiliumPortProtocol, CiliumPortRule,
 };
+pub use event::{Event, EventSpec};
 pub use gateway::{
     BackendRef, HTTPBackendRef, HTTPRoute, HTTPRouteRule, HTTPRouteSpec, ParentReference, TLSRoute,
     TLSRouteRule, TLSRouteSpec,
 };
+pub use warm_pool::{WarmPool, WarmPoolSpec, WarmPoolStatus};
@@ -0,0 +1,55 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::crds::ChallengeRef;
+
+/// WarmPool keeps pre-provisioned instances of a challenge running
+/// Matching ChallengeInstances claim a spare instead of waiting for a new namespace
+#[derive(CustomResource, Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "berg.norelect.ch",
+    version = "v1",
+    kind = "WarmPool",
+    plural = "warmpools",
+    singular = "warmpool",
+    namespaced = false,
+    status = "WarmPoolStatus",
+    printcolumn = r#"{"name":"Challenge", "type":"string", "jsonPath":".spec.challengeRef.name"}"#,
+    printcolumn = r#"{"name":"Size", "type":"integer", "jsonPath":".spec.size"}"#,
+    printcolumn = r#"{"name":"Ready", "type":"integer", "jsonPath":".status.ready"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct WarmPoolSpec {
+    /// Challenge to keep spares of
+    pub challenge_ref: ChallengeRef,
+
+    /// ChallengeInstanceClass of the spares, only instances of this class claim them
+    /// If not specified, the default class will be used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_class: Option<String>,
+
+    /// Number of spares to keep
+    #[schemars(range(min = 0))]
+    pub size: i32,
+
+    /// Lifetime of an unclaimed spare before it is replaced (e.g., "24h")
+    #[serde(default = "default_spare_timeout")]
+    #[schemars(regex(pattern = r"^([0-9]+h)?([0-9]+m)?([0-9]+s)?$"))]
+    pub spare_timeout: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WarmPoolStatus {
+    /// Spares that are running and can be claimed
+    pub ready: i32,
+
+    /// Spares in any phase, including those still starting
+    pub total: i32,
+}
+
+fn default_spare_timeout() -> String {
+    "24h".to_string()
+}
@@ -16,6 +16,19 @@ pub enum Error {
     #[error("ChallengeInstanceClass not found: {name}")]
     InstanceClassNotFound { name: String },
 
+    #[error("Flag secret not found: {namespace}/{name}")]
+    FlagSecretNotFound { namespace: String, name: String },
+
+    #[error("Flag secret {namespace}/{name} has no key {key}")]
+    FlagSecretKeyMissing {
+        namespace: String,
+        name: String,
+        key: String,
+    },
+
+    #[error("Flag secret {namespace}/{name} is outside the allowed flag secret namespaces")]
+    FlagSecretForbidden { namespace: String, name: String },
+
     #[error("Flag validation failed: {0}")]
     FlagValidationError(String),
 
@@ -37,6 +50,15 @@ pub enum Error {
     #[error("Finalizer error: {0}")]
     FinalizerError(String),
 
+    #[error("Namespace {name} belongs to another instance")]
+    NamespaceCollision { name: String },
+
+    #[error("Instance {name} has no namespace in its status")]
+    NamespaceNotRecorded { name: String },
+
+    #[error("Instance {name} has no UID yet")]
+    UidNotAssigned { name: String },
+
     #[error("Waiting for dependencies to become ready")]
     ProgressingWait,
 }
@@ -1,22 +1,17 @@
-use crate::{
-    crds::ContentFlag,
-    error::{Error, Result},
-};
+use crate::{crds::ContentFlag, error::Result};
 use k8s_openapi::api::core::v1::{ConfigMapVolumeSource, KeyToPath, Volume, VolumeMount};
 
 /// Build volume and mount for content flag
-pub fn build_volume_mount(config: &ContentFlag, _flag: &str) -> Result<(Volume, VolumeMount)> {
-    let path_with_entropy = crate::flag::entropy::substitute_entropy(&config.path);
-    let filename = std::path::Path::new(&path_with_entropy)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or_else(|| Error::FlagGenerationError("Invalid path".into()))?
-        .to_string();
+/// path is the flag path with any {entropy} placeholder already resolved
+pub fn build_volume_mount(config: &ContentFlag, path: &str) -> Result<(Volume, VolumeMount)> {
+    let filename = crate::flag::file_name(path)?;
+
+    let name = crate::flag::object_name("content", config.flag_name.as_deref());
 
     let volume = Volume {
-        name: "flag-content".to_string(),
+        name: name.clone(),
         config_map: Some(ConfigMapVolumeSource {
-            name: "flag-content".to_string(),
+            name: name.clone(),
             items: Some(vec![KeyToPath {
                 key: "content".to_string(),
                 path: filename.clone(),
@@ -28,10 +23,12 @@ pub fn build_volume_mount(config: &ContentFlag, _flag: &str) -> Result<(Volume,
         ..Default::default()
     };
 
+    let (mount_path, sub_path) = crate::flag::mount_target(path, config.mount_directory)?;
+
     let mount = VolumeMount {
-        name: "flag-content".to_string(),
-        mount_path: path_with_entropy,
-        sub_path: Some(filename),
+        name,
+        mount_path,
+        sub_path,
         read_only: Some(true),
         ..Default::default()
     };
@@ -0,0 +1,54 @@
+use crate::crds::CookieFlag;
+use k8s_openapi::api::core::v1::EnvVar;
+
+/// Environment variable carrying the cookies of a bot
+pub const COOKIE_ENV: &str = "BOT_COOKIES";
+
+/// Build the environment variable handing a flag cookie to a bot
+/// The value is a JSON list of cookies, which headless browsers accept as is
+pub fn build_env(config: &CookieFlag, flag: &str) -> EnvVar {
+    let mut cookie = serde_json::json!({
+        "name": config.name,
+        "value": flag,
+        "path": config.path.as_deref().unwrap_or("/"),
+        "httpOnly": config.http_only,
+    });
+    if let Some(ref domain) = config.domain {
+        cookie["domain"] = serde_json::json!(domain);
+    }
+
+    EnvVar {
+        name: COOKIE_ENV.to_string(),
+        value: Some(serde_json::json!([cookie]).to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_env() {
+        let config: CookieFlag = serde_json::from_value(serde_json::json!({
+            "name": "session",
+            "domain": "web",
+            "httpOnly": true
+        }))
+        .unwrap();
+
+        let env = build_env(&config, "flag{cookie}");
+        assert_eq!(env.name, COOKIE_ENV);
+        let cookies: serde_json::Value = serde_json::from_str(&env.value.unwrap()).unwrap();
+        assert_eq!(
+            cookies,
+            serde_json::json!([{
+                "name": "session",
+                "value": "flag{cookie}",
+                "domain": "web",
+                "path": "/",
+                "httpOnly": true
+            }])
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use crate::crds::Challenge;
 use rand::Rng;
+use std::collections::BTreeMap;
 
 /// Substitute {entropy} placeholder in a path with 12 random hex characters
 pub fn substitute_entropy(path: &str) -> String {
@@ -13,6 +15,46 @@ pub fn substitute_entropy(path: &str) -> String {
     path.replace("{entropy}", &entropy)
 }
 
+/// Key of a resolved flag path, e.g. "web/content"
+pub fn path_key(hostname: &str, kind: &str) -> String {
+    format!("{}/{}", hostname, kind)
+}
+
+/// Resolve the flag paths of every container, keeping paths that were already resolved
+/// so the flag does not move when a deployment is rebuilt
+pub fn resolve_paths(
+    challenge: &Challenge,
+    existing: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut paths = BTreeMap::new();
+
+    for container in &challenge.spec.containers {
+        let Some(ref dynamic_flag) = container.dynamic_flag else {
+            continue;
+        };
+
+        let configured = [
+            ("content", dynamic_flag.content.as_ref().map(|c| &c.path)),
+            (
+                "executable",
+                dynamic_flag.executable.as_ref().map(|e| &e.path),
+            ),
+        ];
+        for (kind, path) in configured {
+            if let Some(path) = path {
+                let key = path_key(&container.hostname, kind);
+                let resolved = existing
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| substitute_entropy(path));
+                paths.insert(key, resolved);
+            }
+        }
+    }
+
+    paths
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,4 +77,26 @@ mod tests {
         let path = "/home/ctf/flag.txt";
         assert_eq!(substitute_entropy(path), path);
     }
+
+    #[test]
+    fn test_resolve_paths_keeps_existing() {
+        let challenge = crate::test_fixtures::challenge(serde_json::json!({
+            "containers": [{
+                "hostname": "web",
+                "image": "nginx",
+                "dynamicFlag": {
+                    "content": { "path": "/srv/{entropy}/flag.txt" },
+                    "executable": { "path": "/readflag" }
+                }
+            }]
+        }));
+
+        let first = resolve_paths(&challenge, &BTreeMap::new());
+        assert_eq!(first.len(), 2);
+        assert_eq!(first["web/executable"], "/readflag");
+        assert!(!first["web/content"].contains("{entropy}"));
+
+        let second = resolve_paths(&challenge, &first);
+        assert_eq!(first, second);
+    }
 }
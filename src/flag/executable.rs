@@ -1,22 +1,17 @@
-use crate::{
-    crds::ExecutableFlag,
-    error::{Error, Result},
-};
+use crate::{crds::ExecutableFlag, error::Result};
 use k8s_openapi::api::core::v1::{ConfigMapVolumeSource, KeyToPath, Volume, VolumeMount};
 
 /// Build volume and mount for executable flag
-pub fn build_volume_mount(config: &ExecutableFlag, _flag: &str) -> Result<(Volume, VolumeMount)> {
-    let path_with_entropy = crate::flag::entropy::substitute_entropy(&config.path);
-    let filename = std::path::Path::new(&path_with_entropy)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or_else(|| Error::FlagGenerationError("Invalid path".into()))?
-        .to_string();
+/// path is the flag path with any {entropy} placeholder already resolved
+pub fn build_volume_mount(config: &ExecutableFlag, path: &str) -> Result<(Volume, VolumeMount)> {
+    let filename = crate::flag::file_name(path)?;
+
+    let name = crate::flag::object_name("executable", config.flag_name.as_deref());
 
     let volume = Volume {
-        name: "flag-executable".to_string(),
+        name: name.clone(),
         config_map: Some(ConfigMapVolumeSource {
-            name: "flag-executable".to_string(),
+            name: name.clone(),
             items: Some(vec![KeyToPath {
                 key: "executable".to_string(),
                 path: filename.clone(),
@@ -28,10 +23,12 @@ pub fn build_volume_mount(config: &ExecutableFlag, _flag: &str) -> Result<(Volum
         ..Default::default()
     };
 
+    let (mount_path, sub_path) = crate::flag::mount_target(path, config.mount_directory)?;
+
     let mount = VolumeMount {
-        name: "flag-executable".to_string(),
-        mount_path: path_with_entropy,
-        sub_path: Some(filename),
+        name,
+        mount_path,
+        sub_path,
         read_only: Some(true),
         ..Default::default()
     };
@@ -156,7 +153,7 @@ mod tests {
     use std::{
         fs::{File, Permissions},
         io::Write,
-        os::{self, unix::fs::PermissionsExt},
+        os::unix::fs::PermissionsExt,
         path::Path,
         process::Command,
     };
@@ -208,13 +205,13 @@ mod tests {
 
         let path = Path::new("/dev/shm/elf");
         {
-            let mut file = File::create(&path).unwrap();
+            let mut file = File::create(path).unwrap();
             file.set_permissions(Permissions::from_mode(0o777)).unwrap();
             file.write_all(&elf).unwrap();
             file.flush().unwrap();
         }
 
-        let result = Command::new(&path).output().unwrap();
+        let result = Command::new(path).output().unwrap();
         // std::fs::remove_file(&path).unwrap();
         assert!(flag == String::from_utf8(result.stdout).unwrap());
     }
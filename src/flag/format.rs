@@ -0,0 +1,100 @@
+use crate::error::{Error, Result};
+use regex::Regex;
+
+/// Wildcard placeholder in flag formats, matches one or more characters
+const WILDCARD: &str = "...";
+
+/// Prefix marking a flag format as a regular expression
+const REGEX_PREFIX: &str = "regex:";
+
+/// Validate a flag against a challenge flag format
+///
+/// A format is either a literal with "..." wildcards (e.g. "flag{...}") or a regular
+/// expression prefixed with "regex:" (e.g. "regex:^flag\{[0-9a-f]{32}\}$")
+pub fn validate(format: &str, flag: &str) -> Result<()> {
+    let matches = if let Some(pattern) = format.strip_prefix(REGEX_PREFIX) {
+        Regex::new(pattern)
+            .map_err(|e| Error::FlagValidationError(format!("Invalid flag format regex: {}", e)))?
+            .is_match(flag)
+    } else {
+        matches_wildcard(format, flag)
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(Error::FlagValidationError(format!(
+            "Flag does not match format {}",
+            format
+        )))
+    }
+}
+
+/// Match a literal format where every "..." stands for one or more characters
+fn matches_wildcard(format: &str, flag: &str) -> bool {
+    let segments: Vec<&str> = format.split(WILDCARD).collect();
+    if segments.len() == 1 {
+        return format == flag;
+    }
+
+    let prefix = segments[0];
+    let suffix = segments[segments.len() - 1];
+    if flag.len() < prefix.len() + suffix.len() || !flag.starts_with(prefix) {
+        return false;
+    }
+    if !flag.ends_with(suffix) {
+        return false;
+    }
+
+    // every wildcard consumes at least one character, so literals are matched greedily
+    // from the left with a one character gap between them
+    let mut rest = &flag[prefix.len()..flag.len() - suffix.len()];
+    for literal in &segments[1..segments.len() - 1] {
+        let Some(skip) = rest.char_indices().nth(1).map(|(i, _)| i) else {
+            return false;
+        };
+        match rest[skip..].find(literal) {
+            Some(pos) => rest = &rest[skip + pos + literal.len()..],
+            None => return false,
+        }
+    }
+
+    !rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_format() {
+        assert!(validate("flag{...}", "flag{hello}").is_ok());
+        assert!(validate("flag{...}", "flag{}").is_err());
+        assert!(validate("flag{...}", "FLAG{hello}").is_err());
+        assert!(validate("flag{...}", "flag{hello").is_err());
+        assert!(validate("flag{...}", "xflag{hello}").is_err());
+        assert!(validate("flag{...}", "flag{hello}x").is_err());
+    }
+
+    #[test]
+    fn test_multiple_wildcards() {
+        assert!(validate("flag{...-...}", "flag{ab-cd}").is_ok());
+        assert!(validate("flag{...-...}", "flag{ab-}").is_err());
+        assert!(validate("flag{...-...}", "flag{-cd}").is_err());
+        assert!(validate("flag{...-...}", "flag{a-b-c}").is_ok());
+        assert!(validate("...{...}", "cscg{x}").is_ok());
+    }
+
+    #[test]
+    fn test_literal_format() {
+        assert!(validate("flag{static}", "flag{static}").is_ok());
+        assert!(validate("flag{static}", "flag{other}").is_err());
+    }
+
+    #[test]
+    fn test_regex_format() {
+        assert!(validate(r"regex:^flag\{[0-9a-f]{4}\}$", "flag{beef}").is_ok());
+        assert!(validate(r"regex:^flag\{[0-9a-f]{4}\}$", "flag{nope}").is_err());
+        assert!(validate(r"regex:^flag\{(", "flag{beef}").is_err());
+    }
+}
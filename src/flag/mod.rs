@@ -1,3 +1,147 @@
+use crate::{
+    crds::DynamicFlag,
+    error::{Error, Result},
+};
+use std::collections::BTreeMap;
+
 pub mod content;
+pub mod cookie;
 pub mod entropy;
 pub mod executable;
+pub mod format;
+
+/// Flags resolved for an instance: the default flag plus any named stage flags
+#[derive(Clone, Debug, Default)]
+pub struct InstanceFlags {
+    pub default: String,
+    pub named: BTreeMap<String, String>,
+}
+
+impl InstanceFlags {
+    /// Look up a flag by name, None selects the default flag
+    pub fn get(&self, name: Option<&str>) -> Result<&str> {
+        match name {
+            None => Ok(&self.default),
+            Some(name) => self
+                .named
+                .get(name)
+                .map(|f| f.as_str())
+                .ok_or_else(|| Error::FlagValidationError(format!("Unknown flag {}", name))),
+        }
+    }
+
+    /// Iterate over all flags, the default flag is reported without a name
+    pub fn iter(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+        std::iter::once((None, self.default.as_str())).chain(
+            self.named
+                .iter()
+                .map(|(name, flag)| (Some(name.as_str()), flag.as_str())),
+        )
+    }
+}
+
+/// Names of the flags a container consumes, None being the default flag
+pub fn referenced_names(dynamic_flag: &DynamicFlag) -> impl Iterator<Item = Option<&str>> {
+    [
+        dynamic_flag.env.as_ref().map(|e| e.flag_name.as_deref()),
+        dynamic_flag.cookie.as_ref().map(|c| c.flag_name.as_deref()),
+        dynamic_flag
+            .content
+            .as_ref()
+            .map(|c| c.flag_name.as_deref()),
+        dynamic_flag
+            .executable
+            .as_ref()
+            .map(|e| e.flag_name.as_deref()),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// Name of the ConfigMap and volume holding a flag of the given kind
+/// e.g. "flag-content" for the default flag and "flag-content-root" for the "root" flag
+pub fn object_name(kind: &str, flag_name: Option<&str>) -> String {
+    match flag_name {
+        None => format!("flag-{}", kind),
+        Some(name) => format!("flag-{}-{}", kind, name),
+    }
+}
+
+/// Check if a flag name yields valid ConfigMap and volume names
+/// Volume names must be DNS labels, so the composed name is checked rather than the flag name
+pub fn is_valid_name(name: &str) -> bool {
+    ["content", "executable"]
+        .iter()
+        .all(|kind| crate::utils::is_dns_label(&object_name(kind, Some(name))))
+}
+
+/// File name of a flag path
+pub fn file_name(path: &str) -> Result<String> {
+    std::path::Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.to_string())
+        .ok_or_else(|| Error::FlagGenerationError("Invalid path".into()))
+}
+
+/// Mount path and subPath of a flag file
+/// Without a subPath the kubelet keeps the projected file in sync with the ConfigMap,
+/// but the mount replaces the whole parent directory, hiding the files the image ships there
+pub fn mount_target(path: &str, mount_directory: bool) -> Result<(String, Option<String>)> {
+    if !mount_directory {
+        return Ok((path.to_string(), Some(file_name(path)?)));
+    }
+
+    let parent = std::path::Path::new(path)
+        .parent()
+        .and_then(|p| p.to_str())
+        .filter(|p| !p.is_empty() && *p != "/")
+        .ok_or_else(|| Error::FlagGenerationError("Invalid path".into()))?;
+    Ok((parent.to_string(), None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_flags_get() {
+        let flags = InstanceFlags {
+            default: "flag{user}".to_string(),
+            named: BTreeMap::from([("root".to_string(), "flag{root}".to_string())]),
+        };
+        assert_eq!(flags.get(None).unwrap(), "flag{user}");
+        assert_eq!(flags.get(Some("root")).unwrap(), "flag{root}");
+        assert!(flags.get(Some("stage2")).is_err());
+        assert_eq!(flags.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_object_name() {
+        assert_eq!(object_name("content", None), "flag-content");
+        assert_eq!(object_name("content", Some("root")), "flag-content-root");
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("root"));
+        assert!(!is_valid_name("Root"));
+        // "flag-executable-" leaves 47 characters of a 63 character label
+        assert!(is_valid_name(&"a".repeat(47)));
+        assert!(!is_valid_name(&"a".repeat(48)));
+    }
+
+    #[test]
+    fn test_mount_target() {
+        assert_eq!(
+            mount_target("/app/flag.txt", false).unwrap(),
+            ("/app/flag.txt".to_string(), Some("flag.txt".to_string()))
+        );
+        assert_eq!(
+            mount_target("/flag/flag.txt", true).unwrap(),
+            ("/flag".to_string(), None)
+        );
+        // mounting over the root directory would hide the whole image
+        assert!(mount_target("/flag.txt", true).is_err());
+    }
+}
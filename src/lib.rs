@@ -6,4 +6,6 @@ pub mod flag;
 pub mod reconciler;
 pub mod resources;
 pub mod telemetry;
+#[cfg(test)]
+mod test_fixtures;
 pub mod utils;
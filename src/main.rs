@@ -1,16 +1,18 @@
 use berg_operator::{
     config::ControllerConfig,
-    crds::{ChallengeInstance, CiliumNetworkPolicy, HTTPRoute, TLSRoute},
-    reconciler::{self, Context},
+    crds::{ChallengeInstance, CiliumNetworkPolicy, HTTPRoute, TLSRoute, WarmPool},
+    reconciler::{self, warm_pool, Context},
     telemetry::{self, Metrics},
 };
 use futures::StreamExt;
 use k8s_openapi::api::{
     apps::v1::Deployment,
     core::v1::{ConfigMap, Namespace, Secret, Service},
+    networking::v1::NetworkPolicy,
     policy::v1::PodDisruptionBudget,
 };
 use kube::{
+    api::ListParams,
     runtime::{controller::Controller, watcher::Config as WatcherConfig},
     Api, Client,
 };
@@ -43,6 +45,7 @@ async fn main() -> anyhow::Result<()> {
     let deployments = Api::<Deployment>::all(client.to_owned());
     let secrets = Api::<Secret>::all(client.to_owned()); // used for pull secrets :/
     let np = Api::<CiliumNetworkPolicy>::all(client.to_owned());
+    let k8s_np = Api::<NetworkPolicy>::all(client.to_owned());
     let pdb = Api::<PodDisruptionBudget>::all(client.to_owned());
     let services = Api::<Service>::all(client.to_owned());
     let http_routes = Api::<HTTPRoute>::all(client.to_owned());
@@ -59,20 +62,31 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    // warm pools own their spare instances
+    let pools = Api::<WarmPool>::all(client.to_owned());
+    let pool_instances = Api::<ChallengeInstance>::all(client.to_owned());
+
     info!("Starting controller loop");
-    Controller::new(instances, WatcherConfig::default())
+    let mut instance_controller = Controller::new(instances, WatcherConfig::default())
         .owns(namespaces, WatcherConfig::default())
         .owns(config_maps, WatcherConfig::default())
         .owns(deployments, WatcherConfig::default())
         .owns(secrets, WatcherConfig::default())
-        .owns(np, WatcherConfig::default())
+        .owns(k8s_np, WatcherConfig::default())
         .owns(pdb, WatcherConfig::default())
         .owns(services, WatcherConfig::default())
         .owns(http_routes, WatcherConfig::default())
-        .owns(tls_routes, WatcherConfig::default())
+        .owns(tls_routes, WatcherConfig::default());
+    // clusters using only the Kubernetes policy backend may not run Cilium
+    if np.list(&ListParams::default().limit(1)).await.is_ok() {
+        instance_controller = instance_controller.owns(np, WatcherConfig::default());
+    } else {
+        info!("CiliumNetworkPolicies are not served, not watching them");
+    }
+    let instance_controller = instance_controller
         .reconcile_all_on(reload_rx.map(|_| ()))
         .shutdown_on_signal()
-        .run(reconciler::reconcile, reconciler::error_policy, ctx)
+        .run(reconciler::reconcile, reconciler::error_policy, ctx.clone())
         .for_each(|res| async move {
             match res {
                 Ok(o) => debug!("Reconciled: {:?}", o),
@@ -80,8 +94,21 @@ async fn main() -> anyhow::Result<()> {
                 Err(kube::runtime::controller::Error::ObjectNotFound(_)) => {}
                 Err(e) => tracing::warn!("[!] Reconciliation error: {:?}", e),
             }
-        })
-        .await;
+        });
+
+    let pool_controller = Controller::new(pools, WatcherConfig::default())
+        .owns(pool_instances, WatcherConfig::default())
+        .shutdown_on_signal()
+        .run(warm_pool::reconcile_pool, warm_pool::error_policy, ctx)
+        .for_each(|res| async move {
+            match res {
+                Ok(o) => debug!("Reconciled warm pool: {:?}", o),
+                Err(kube::runtime::controller::Error::ObjectNotFound(_)) => {}
+                Err(e) => tracing::warn!("[!] Warm pool reconciliation error: {:?}", e),
+            }
+        });
+
+    futures::future::join(instance_controller, pool_controller).await;
 
     let _ = shutdown_tx.send(());
     let _ = handle.join();
@@ -0,0 +1,89 @@
+use crate::{
+    crds::{ChallengeInstance, Event},
+    error::Result,
+};
+use chrono::{DateTime, Utc};
+use kube::api::Api;
+
+use super::Context;
+
+/// Where an event is in its lifecycle
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventWindow {
+    NotStarted,
+    Open,
+    Frozen,
+    Ended,
+}
+
+/// Determine the window of an event at the given time
+pub fn window(event: &Event, now: DateTime<Utc>) -> EventWindow {
+    if now < event.spec.starts_at.0 {
+        EventWindow::NotStarted
+    } else if now >= event.spec.ends_at.0 {
+        EventWindow::Ended
+    } else if event.spec.freezes_at.as_ref().is_some_and(|f| now >= f.0) {
+        EventWindow::Frozen
+    } else {
+        EventWindow::Open
+    }
+}
+
+/// Fetch an event by name
+/// Events used to be free-form strings, so a missing Event resource is not an error
+pub async fn fetch_event(name: &str, ctx: &Context) -> Result<Option<Event>> {
+    let events: Api<Event> = Api::all(ctx.client.clone());
+    Ok(events.get_opt(name).await?)
+}
+
+/// Check if the event an instance belongs to has ended
+pub async fn has_ended(instance: &ChallengeInstance, ctx: &Context) -> Result<bool> {
+    let Some(name) = instance.status.as_ref().and_then(|s| s.event.as_ref()) else {
+        return Ok(false);
+    };
+
+    Ok(fetch_event(name, ctx)
+        .await?
+        .is_some_and(|event| window(&event, Utc::now()) == EventWindow::Ended))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_window() {
+        let event: Event = serde_json::from_value(serde_json::json!({
+            "apiVersion": "berg.norelect.ch/v1",
+            "kind": "Event",
+            "metadata": { "name": "ctf" },
+            "spec": {
+                "startsAt": "2026-01-01T10:00:00Z",
+                "freezesAt": "2026-01-02T08:00:00Z",
+                "endsAt": "2026-01-02T10:00:00Z"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            window(&event, at("2026-01-01T09:59:59Z")),
+            EventWindow::NotStarted
+        );
+        assert_eq!(
+            window(&event, at("2026-01-01T10:00:00Z")),
+            EventWindow::Open
+        );
+        assert_eq!(
+            window(&event, at("2026-01-02T09:00:00Z")),
+            EventWindow::Frozen
+        );
+        assert_eq!(
+            window(&event, at("2026-01-02T10:00:00Z")),
+            EventWindow::Ended
+        );
+    }
+}
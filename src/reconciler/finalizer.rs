@@ -1,9 +1,9 @@
-use super::{update_status, Context, FINALIZER};
+use super::{shared, update_status, warm_pool, Context, FINALIZER};
 use crate::{
     crds::{ChallengeInstance, Condition, ConditionStatus, Phase},
     date_time::DateTime,
     error::Result,
-    utils,
+    resources,
 };
 use k8s_openapi::api::{
     apps::v1::Deployment,
@@ -20,27 +20,81 @@ use tracing::{debug, info};
 pub async fn cleanup(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> Result<Action> {
     debug!("Cleaning up ChallengeInstance {}", instance.name_any());
 
-    let namespace_name = if let Some(ref status) = instance.status {
-        if let Some(ref ns) = status.namespace {
-            ns.clone()
-        } else {
-            utils::generate_namespace_name(
-                &ctx.config.namespace_prefix,
-                &instance.spec.challenge_ref.name,
-                &instance.spec.owner_id,
-            )
-        }
-    } else {
-        utils::generate_namespace_name(
-            &ctx.config.namespace_prefix,
-            &instance.spec.challenge_ref.name,
-            &instance.spec.owner_id,
-        )
-    };
+    // A claimed spare handed its namespace over to the claiming instance
+    if warm_pool::is_claimed(&instance) {
+        debug!(
+            "Spare {} was claimed, keeping its resources",
+            instance.name_any()
+        );
+        remove_finalizer(&instance, &ctx).await?;
+        ctx.metrics.decr_active_instances();
+        return Ok(Action::await_change());
+    }
+
+    // Instances bound to a shared instance own no resources, but may be its last binding
+    if let Some(shared_name) = shared::binding_of(&instance) {
+        shared::unbind(&instance, shared_name, &ctx).await?;
+
+        update_status(&instance, &ctx, |status| {
+            status.phase = Some(Phase::Terminated);
+            status.terminated_at = Some(DateTime::now());
+        })
+        .await?;
+        remove_finalizer(&instance, &ctx).await?;
+
+        ctx.metrics.decr_active_instances();
+        return Ok(Action::await_change());
+    }
+
+    let namespace_name = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.namespace.clone())
+        .unwrap_or_else(|| super::namespace_name(&instance, &ctx));
+
+    // Never tear down a namespace that a newer instance of the same owner now uses
+    let namespaces: Api<Namespace> = Api::all(ctx.client.clone());
+    let foreign = namespaces
+        .get_opt(&namespace_name)
+        .await?
+        .is_some_and(|ns| !resources::namespace::is_owned_by(&ns, &instance));
+    if foreign {
+        debug!(
+            "Namespace {} belongs to another instance, leaving it",
+            namespace_name
+        );
+    } else if let Some(action) = delete_resources(&namespace_name, &ctx).await? {
+        return Ok(action);
+    }
+
+    // Update status to Terminated
+    let now = DateTime::now();
+    update_status(&instance, &ctx, |status| {
+        status.phase = Some(Phase::Terminated);
+        status.terminated_at = Some(now.clone());
+        status.conditions.push(Condition {
+            r#type: "NamespaceDeleted".to_string(),
+            status: ConditionStatus::True,
+            last_transition_time: Some(now),
+            reason: Some("Deleted".to_string()),
+            message: Some("Namespace deleted".to_string()),
+        });
+    })
+    .await?;
+
+    // Remove finalizer
+    remove_finalizer(&instance, &ctx).await?;
 
+    ctx.metrics.decr_active_instances();
+    Ok(Action::await_change())
+}
+
+/// Delete the workloads and then the namespace of an instance
+/// Returns an action while deletion is still in progress
+async fn delete_resources(namespace_name: &str, ctx: &Context) -> Result<Option<Action>> {
     // Clean up workloads before cleaning up NetworkPolicies
-    let deployments_api: Api<Deployment> = Api::namespaced(ctx.client.clone(), &namespace_name);
-    let pods_api: Api<Pod> = Api::namespaced(ctx.client.clone(), &namespace_name);
+    let deployments_api: Api<Deployment> = Api::namespaced(ctx.client.clone(), namespace_name);
+    let pods_api: Api<Pod> = Api::namespaced(ctx.client.clone(), namespace_name);
     let mut deleting = false;
     for deploy in deployments_api.list(&ListParams::default()).await? {
         deleting = true;
@@ -59,13 +113,18 @@ pub async fn cleanup(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> Res
 
     if deleting {
         // wait for resources to be deleted before continuing
-        return Ok(Action::requeue(Duration::from_secs(2)));
+        return Ok(Some(Action::requeue(Duration::from_secs(2))));
+    }
+
+    // Release persistent volumes explicitly, their storage may outlive the namespace otherwise
+    if resources::volume::delete_claims(namespace_name, ctx).await? {
+        return Ok(Some(Action::requeue(Duration::from_secs(2))));
     }
 
     // Delete namespace (cascades to all resources)
     let namespaces: Api<Namespace> = Api::all(ctx.client.clone());
 
-    match namespaces.get(&namespace_name).await {
+    match namespaces.get(namespace_name).await {
         Ok(namespace) => {
             if !namespace
                 .status
@@ -74,13 +133,13 @@ pub async fn cleanup(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> Res
                 .unwrap_or_default()
             {
                 namespaces
-                    .delete(&namespace_name, &DeleteParams::default())
+                    .delete(namespace_name, &DeleteParams::default())
                     .await?;
                 info!("Deleted namespace {}", namespace_name);
-                return Ok(Action::requeue(Duration::from_secs(2)));
+                return Ok(Some(Action::requeue(Duration::from_secs(2))));
             } else {
                 debug!("Namespace {} already terminating", namespace_name);
-                return Ok(Action::requeue(Duration::from_secs(2)));
+                return Ok(Some(Action::requeue(Duration::from_secs(2))));
             }
         }
         Err(kube::Error::Api(ae)) if ae.code == 404 => {
@@ -89,26 +148,7 @@ pub async fn cleanup(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> Res
         Err(e) => return Err(e.into()),
     }
 
-    // Update status to Terminated
-    let now = DateTime::now();
-    update_status(&instance, &ctx, |status| {
-        status.phase = Some(Phase::Terminated);
-        status.terminated_at = Some(now.clone());
-        status.conditions.push(Condition {
-            r#type: "NamespaceDeleted".to_string(),
-            status: ConditionStatus::True,
-            last_transition_time: Some(now),
-            reason: Some("Deleted".to_string()),
-            message: Some("Namespace deleted".to_string()),
-        });
-    })
-    .await?;
-
-    // Remove finalizer
-    remove_finalizer(&instance, &ctx).await?;
-
-    ctx.metrics.decr_active_instances();
-    Ok(Action::await_change())
+    Ok(None)
 }
 
 async fn remove_finalizer(instance: &ChallengeInstance, ctx: &Context) -> Result<()> {
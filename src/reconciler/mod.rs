@@ -1,10 +1,16 @@
 use crate::{
-    config::ControllerConfig,
-    crds::{Challenge, ChallengeInstance, ChallengeInstanceClass, ChallengeInstanceStatus, Phase},
+    config::{ControllerConfig, NamespaceNaming},
+    crds::{
+        Challenge, ChallengeInstance, ChallengeInstanceClass, ChallengeInstanceStatus, Phase,
+        SecretKeyRef, TerminationReason,
+    },
     date_time::DateTime,
     error::{Error, Result},
+    flag::InstanceFlags,
     telemetry::Metrics,
+    utils,
 };
+use k8s_openapi::api::core::v1::Secret;
 use kube::{
     api::{Api, Patch, PatchParams},
     client::Client,
@@ -13,14 +19,28 @@ use kube::{
 };
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::{debug, instrument, warn};
+use tracing::{debug, info, instrument, warn};
 
+pub mod event;
 pub mod finalizer;
+pub mod release;
+pub mod reset;
+pub mod rotation;
+pub mod security;
+pub mod shared;
 pub mod state;
+pub mod suspend;
 pub mod timeout;
+pub mod warm_pool;
 
 pub const FINALIZER: &str = "challengeinstance.berg.norelect.ch/finalizer";
 
+/// Owner of the instances the controller keeps for itself, warm pool spares and shared instances
+pub const CONTROLLER_OWNER_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Field manager of objects the controller keeps up to date with server-side apply
+pub const FIELD_MANAGER: &str = "berg-controller";
+
 #[derive(Clone)]
 pub struct Context {
     pub client: Client,
@@ -61,6 +81,16 @@ pub async fn reconcile(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> R
         return initialize_instance(instance, ctx).await;
     }
 
+    // Terminate instances whose event is over, before they are reported as timed out
+    if !matches!(
+        instance.status.as_ref().and_then(|s| s.phase.as_ref()),
+        Some(Phase::Terminating | Phase::Terminated | Phase::Failed)
+    ) && event::has_ended(&instance, &ctx).await?
+    {
+        info!("Event of instance {} has ended, terminating", name);
+        return timeout::terminate(instance, ctx, TerminationReason::EventEnded).await;
+    }
+
     // Check timeout expiration
     if timeout::is_expired(&instance) {
         return timeout::terminate_expired(instance, ctx).await;
@@ -77,11 +107,31 @@ pub async fn reconcile(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> R
         .and_then(|s| s.phase.as_ref())
         .unwrap_or(&Phase::Pending);
 
+    // Push a changed flag, a requested reset or a suspension to instances that already have
+    // resources, instances bound to a shared instance have none of their own
+    let bound = instance
+        .status
+        .as_ref()
+        .is_some_and(|s| s.shared_instance.is_some());
+    if matches!(phase, Phase::Starting | Phase::Running) && !bound {
+        let flags = fetch_flags(&instance, &ctx).await?;
+        if rotation::flag_changed(&ctx.config.flag_hash_key, &instance, &flags) {
+            return rotation::rotate_flag(instance, &challenge, &flags, ctx).await;
+        }
+        if reset::reset_requested(&instance) {
+            return reset::reset(instance, &challenge, &flags, ctx).await;
+        }
+        if instance.spec.suspended {
+            return suspend::suspend(instance, ctx).await;
+        }
+    }
+
     match phase {
         Phase::Pending => state::reconcile_pending(instance, challenge, class, ctx).await,
         Phase::Creating => state::reconcile_creating(instance, challenge, class, ctx).await,
         Phase::Starting => state::reconcile_starting(instance, challenge, class, ctx).await,
         Phase::Running => state::reconcile_running(instance, challenge, class, ctx).await,
+        Phase::Suspended => suspend::reconcile_suspended(instance, ctx).await,
         Phase::Terminating => state::reconcile_terminating(instance, ctx).await,
         Phase::Terminated | Phase::Failed => {
             // No action needed
@@ -90,6 +140,49 @@ pub async fn reconcile(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> R
     }
 }
 
+/// Namespace recorded in the status of an instance that already has resources
+/// The status may have been wiped or edited, so a missing namespace is an error
+pub fn recorded_namespace(instance: &ChallengeInstance) -> Result<&str> {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.namespace.as_deref())
+        .ok_or_else(|| Error::NamespaceNotRecorded {
+            name: instance.name_any(),
+        })
+}
+
+/// Name of the namespace holding the resources of an instance
+pub fn namespace_name(instance: &ChallengeInstance, ctx: &Context) -> String {
+    let instance_id = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.instance_id.as_deref());
+
+    match (&ctx.config.namespace_naming, instance_id) {
+        (NamespaceNaming::Hashed, Some(instance_id)) => utils::generate_hashed_namespace_name(
+            &ctx.config.namespace_prefix,
+            &instance.spec.challenge_ref.name,
+            &instance.spec.owner_id,
+            instance_id,
+        ),
+        // spares and shared instances have no real owner, so they are named after their
+        // instance ID
+        (_, Some(instance_id)) if warm_pool::is_spare(instance) || shared::is_shared(instance) => {
+            utils::generate_namespace_name(
+                &ctx.config.namespace_prefix,
+                &instance.spec.challenge_ref.name,
+                instance_id,
+            )
+        }
+        _ => utils::generate_namespace_name(
+            &ctx.config.namespace_prefix,
+            &instance.spec.challenge_ref.name,
+            &instance.spec.owner_id,
+        ),
+    }
+}
+
 async fn fetch_challenge(instance: &ChallengeInstance, ctx: &Context) -> Result<Challenge> {
     let instance_ns = instance.namespace().unwrap().to_string();
     let challenge_ns = instance
@@ -134,6 +227,83 @@ async fn fetch_instance_class(
     })
 }
 
+/// Resolve the flags of an instance, reading the referenced Secret if one is set
+/// The resolved flags are only ever passed to the instance resources, never written back
+pub async fn fetch_flags(instance: &ChallengeInstance, ctx: &Context) -> Result<InstanceFlags> {
+    let default = match instance.spec.flag_secret_ref {
+        Some(ref secret_ref) => {
+            let instance_ns = instance.namespace().unwrap_or_default();
+            let challenge_ns = instance
+                .spec
+                .challenge_ref
+                .namespace
+                .as_deref()
+                .unwrap_or(&instance_ns);
+            let secret_ns = flag_secret_namespace(secret_ref, challenge_ns, &ctx.config)?;
+            let secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), secret_ns);
+            flag_from_secret(secrets.get(&secret_ref.name).await, secret_ns, secret_ref)?
+        }
+        None => instance.spec.flag.clone(),
+    };
+
+    Ok(InstanceFlags {
+        default,
+        named: instance.spec.flags.clone(),
+    })
+}
+
+/// Namespace of the flag secret of an instance
+/// Anyone creating instances could otherwise have any Secret copied into a challenge pod,
+/// so only the configured flag secret namespace and the namespace of the challenge are allowed
+fn flag_secret_namespace<'a>(
+    secret_ref: &'a SecretKeyRef,
+    challenge_ns: &'a str,
+    config: &'a ControllerConfig,
+) -> Result<&'a str> {
+    let allowed = config.flag_secret_namespace.as_deref();
+    let secret_ns = secret_ref
+        .namespace
+        .as_deref()
+        .or(allowed)
+        .unwrap_or(challenge_ns);
+
+    if secret_ns != challenge_ns && Some(secret_ns) != allowed {
+        return Err(Error::FlagSecretForbidden {
+            namespace: secret_ns.to_string(),
+            name: secret_ref.name.clone(),
+        });
+    }
+    Ok(secret_ns)
+}
+
+/// Read the flag out of a fetched flag secret
+fn flag_from_secret(
+    secret: kube::Result<Secret>,
+    secret_ns: &str,
+    secret_ref: &SecretKeyRef,
+) -> Result<String> {
+    let secret = secret.map_err(|e| match e {
+        kube::Error::Api(ae) if ae.code == 404 => Error::FlagSecretNotFound {
+            namespace: secret_ns.to_string(),
+            name: secret_ref.name.clone(),
+        },
+        e => Error::from(e),
+    })?;
+
+    let value = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(&secret_ref.key))
+        .ok_or_else(|| Error::FlagSecretKeyMissing {
+            namespace: secret_ns.to_string(),
+            name: secret_ref.name.clone(),
+            key: secret_ref.key.clone(),
+        })?;
+
+    String::from_utf8(value.0.clone())
+        .map_err(|_| Error::FlagValidationError("Flag secret is not valid UTF-8".to_string()))
+}
+
 async fn add_finalizer(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> Result<Action> {
     let api: Api<ChallengeInstance> = Api::all(ctx.client.clone());
 
@@ -161,19 +331,24 @@ async fn initialize_instance(
     ctx: Arc<Context>,
 ) -> Result<Action> {
     let instance_id = uuid::Uuid::new_v4().to_string();
-    let expires_at = timeout::calculate_expiry(
-        instance
-            .spec
-            .timeout
-            .as_ref()
-            .unwrap_or(&ctx.config.default_timeout),
-    )?;
+    // shared instances live until their last binding ends
+    let expires_at = if shared::is_shared(&instance) {
+        None
+    } else {
+        Some(timeout::calculate_expiry(
+            instance
+                .spec
+                .timeout
+                .as_ref()
+                .unwrap_or(&ctx.config.default_timeout),
+        )?)
+    };
 
     update_status(&instance, &ctx, |status| {
         status.instance_id = Some(instance_id);
         status.phase = Some(Phase::Pending);
         status.started_at = Some(DateTime::now());
-        status.expires_at = Some(DateTime::from(expires_at));
+        status.expires_at = expires_at.map(DateTime::from);
     })
     .await?;
 
@@ -217,3 +392,117 @@ pub fn error_policy(_instance: Arc<ChallengeInstance>, error: &Error, ctx: Arc<C
         Action::requeue(Duration::from_secs(300))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::ByteString;
+    use kube::core::ErrorResponse;
+    use std::collections::BTreeMap;
+
+    fn config(flag_secret_namespace: Option<&str>) -> ControllerConfig {
+        ControllerConfig {
+            default_instance_class: "default".to_string(),
+            default_timeout: "2h".to_string(),
+            namespace_prefix: "ci".to_string(),
+            namespace_naming: NamespaceNaming::Hashed,
+            label_passthrough_prefix: None,
+            flag_secret_namespace: flag_secret_namespace.map(str::to_string),
+            flag_hash_key: b"controller-key".to_vec(),
+        }
+    }
+
+    fn secret_ref(namespace: Option<&str>) -> SecretKeyRef {
+        SecretKeyRef {
+            name: "flags".to_string(),
+            namespace: namespace.map(str::to_string),
+            key: "flag".to_string(),
+        }
+    }
+
+    fn secret(data: &[u8]) -> Secret {
+        Secret {
+            data: Some(BTreeMap::from([(
+                "flag".to_string(),
+                ByteString(data.to_vec()),
+            )])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_flag_secret_namespace() {
+        let config = config(Some("berg-flags"));
+        assert_eq!(
+            flag_secret_namespace(&secret_ref(None), "challenges", &config).unwrap(),
+            "berg-flags"
+        );
+        assert_eq!(
+            flag_secret_namespace(&secret_ref(Some("challenges")), "challenges", &config).unwrap(),
+            "challenges"
+        );
+        assert!(matches!(
+            flag_secret_namespace(&secret_ref(Some("kube-system")), "challenges", &config),
+            Err(Error::FlagSecretForbidden { .. })
+        ));
+
+        // without a configured namespace only the challenge namespace is allowed
+        let config = super::tests::config(None);
+        assert_eq!(
+            flag_secret_namespace(&secret_ref(None), "challenges", &config).unwrap(),
+            "challenges"
+        );
+        assert!(flag_secret_namespace(&secret_ref(Some("berg")), "challenges", &config).is_err());
+    }
+
+    #[test]
+    fn test_flag_from_secret() {
+        let secret_ref = secret_ref(None);
+        assert_eq!(
+            flag_from_secret(Ok(secret(b"flag{secret}")), "challenges", &secret_ref).unwrap(),
+            "flag{secret}"
+        );
+
+        let not_found = kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: "secrets \"flags\" not found".to_string(),
+            reason: "NotFound".to_string(),
+            code: 404,
+        });
+        assert!(matches!(
+            flag_from_secret(Err(not_found), "challenges", &secret_ref),
+            Err(Error::FlagSecretNotFound { .. })
+        ));
+
+        let mut other_key = secret(b"flag{secret}");
+        other_key.data = Some(BTreeMap::from([(
+            "other".to_string(),
+            ByteString(b"flag{secret}".to_vec()),
+        )]));
+        assert!(matches!(
+            flag_from_secret(Ok(other_key), "challenges", &secret_ref),
+            Err(Error::FlagSecretKeyMissing { .. })
+        ));
+
+        assert!(matches!(
+            flag_from_secret(Ok(secret(&[0xff, 0xfe])), "challenges", &secret_ref),
+            Err(Error::FlagValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_recorded_namespace() {
+        let mut instance = crate::test_fixtures::instance();
+        // e.g. after the status was wiped
+        assert!(matches!(
+            recorded_namespace(&instance),
+            Err(Error::NamespaceNotRecorded { .. })
+        ));
+
+        instance.status = Some(ChallengeInstanceStatus {
+            namespace: Some("ci-test-owner".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(recorded_namespace(&instance).unwrap(), "ci-test-owner");
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{
+    crds::{Challenge, ChallengeInstanceClass},
+    error::{Error, Result},
+};
+use chrono::{DateTime, Utc};
+
+/// Time at which a challenge becomes available to instances of the given class
+/// Returns None if the challenge is released or the class ignores hide_until
+pub fn pending_release(
+    challenge: &Challenge,
+    class: &ChallengeInstanceClass,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>> {
+    if class.spec.ignore_hide_until {
+        return Ok(None);
+    }
+
+    let Some(ref hide_until) = challenge.spec.hide_until else {
+        return Ok(None);
+    };
+
+    let release = DateTime::parse_from_rfc3339(hide_until)
+        .map_err(|e| Error::ConfigError(format!("Invalid hideUntil {}: {}", hide_until, e)))?
+        .with_timezone(&Utc);
+
+    Ok((release > now).then_some(release))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn challenge(hide_until: Option<&str>) -> Challenge {
+        crate::test_fixtures::challenge(json!({ "hideUntil": hide_until }))
+    }
+
+    fn class(ignore_hide_until: bool) -> ChallengeInstanceClass {
+        crate::test_fixtures::class(json!({ "ignoreHideUntil": ignore_hide_until }))
+    }
+
+    #[test]
+    fn test_pending_release() {
+        let now = DateTime::parse_from_rfc3339("2026-01-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let hidden = challenge(Some("2026-01-01T13:00:00Z"));
+        assert_eq!(
+            pending_release(&hidden, &class(false), now).unwrap(),
+            Some(now + chrono::Duration::hours(1))
+        );
+        assert_eq!(pending_release(&hidden, &class(true), now).unwrap(), None);
+
+        let released = challenge(Some("2026-01-01T11:00:00Z"));
+        assert_eq!(
+            pending_release(&released, &class(false), now).unwrap(),
+            None
+        );
+        assert_eq!(
+            pending_release(&challenge(None), &class(false), now).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_pending_release_invalid() {
+        assert!(pending_release(&challenge(Some("tomorrow")), &class(false), Utc::now()).is_err());
+    }
+}
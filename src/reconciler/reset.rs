@@ -0,0 +1,98 @@
+use crate::{
+    crds::{Challenge, ChallengeInstance, Condition, ConditionStatus, Phase},
+    date_time::DateTime,
+    error::Result,
+    flag::InstanceFlags,
+    resources,
+};
+use kube::{runtime::controller::Action, ResourceExt};
+use std::{sync::Arc, time::Duration};
+use tracing::info;
+
+use super::{update_status, Context};
+
+/// Check if a reset was requested that has not been handled yet
+pub fn reset_requested(instance: &ChallengeInstance) -> bool {
+    let observed = instance
+        .status
+        .as_ref()
+        .map(|s| s.observed_reset_generation)
+        .unwrap_or_default();
+    instance.spec.reset_generation > observed
+}
+
+/// Restart every workload of an instance, keeping its namespace and endpoints
+/// Persistent volume claims are kept as well, so their data survives the reset
+pub async fn reset(
+    instance: Arc<ChallengeInstance>,
+    challenge: &Challenge,
+    flags: &InstanceFlags,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let namespace = super::recorded_namespace(&instance)?;
+    let generation = instance.spec.reset_generation;
+
+    info!(
+        "Resetting instance {} to generation {}",
+        instance.name_any(),
+        generation
+    );
+
+    // restore the flag objects in case they were tampered with
+    for container in &challenge.spec.containers {
+        if let Some(ref dynamic_flag) = container.dynamic_flag {
+            resources::configmap::update_flag_configmap(dynamic_flag, flags, namespace, &ctx)
+                .await?;
+        }
+    }
+
+    resources::deployment::restart_all(namespace, generation, &ctx).await?;
+
+    let now = DateTime::now();
+    update_status(&instance, &ctx, |status| {
+        status.observed_reset_generation = generation;
+        status.reset_count += 1;
+        // wait for the restarted pods before reporting the instance as running again
+        status.phase = Some(Phase::Starting);
+        status.conditions.retain(|c| c.r#type != "PodsReady");
+        if let Some(cond) = status.conditions.iter_mut().find(|c| c.r#type == "Reset") {
+            cond.status = ConditionStatus::True;
+            cond.last_transition_time = Some(now);
+            cond.message = Some(format!("Reset to generation {}", generation));
+        } else {
+            status.conditions.push(Condition {
+                r#type: "Reset".to_string(),
+                status: ConditionStatus::True,
+                last_transition_time: Some(now),
+                reason: Some("ResetRequested".to_string()),
+                message: Some(format!("Reset to generation {}", generation)),
+            });
+        }
+    })
+    .await?;
+
+    Ok(Action::requeue(Duration::from_secs(2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(reset_generation: i64, observed: Option<i64>) -> ChallengeInstance {
+        let mut instance = crate::test_fixtures::instance();
+        instance.spec.reset_generation = reset_generation;
+        instance.status = observed.map(|observed| crate::crds::ChallengeInstanceStatus {
+            observed_reset_generation: observed,
+            ..Default::default()
+        });
+        instance
+    }
+
+    #[test]
+    fn test_reset_requested() {
+        assert!(!reset_requested(&instance(0, None)));
+        assert!(reset_requested(&instance(1, None)));
+        assert!(reset_requested(&instance(2, Some(1))));
+        assert!(!reset_requested(&instance(1, Some(1))));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    crds::{Challenge, ChallengeInstance, Condition, ConditionStatus, ContainerSpec, DynamicFlag},
+    date_time::DateTime,
+    error::Result,
+    flag::{self, InstanceFlags},
+    resources,
+};
+use hmac::{Hmac, Mac};
+use kube::{runtime::controller::Action, ResourceExt};
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use tracing::info;
+
+use super::{update_status, Context};
+
+/// Hash a flag so it can be compared without storing it in the status
+/// Keyed with a controller secret, so a low-entropy flag cannot be brute-forced from the hash
+pub fn flag_hash(key: &[u8], flag: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(flag.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hash all flags of an instance, named flags included
+pub fn flags_hash(key: &[u8], flags: &InstanceFlags) -> String {
+    let joined: String = flags
+        .iter()
+        .map(|(name, flag)| format!("{}\0{}\0", name.unwrap_or_default(), flag))
+        .collect();
+    flag_hash(key, &joined)
+}
+
+/// Hash only the flags a container consumes, so unrelated flag changes don't restart it
+pub fn container_flag_hash(
+    key: &[u8],
+    dynamic_flag: &DynamicFlag,
+    flags: &InstanceFlags,
+) -> Result<String> {
+    let mut joined = String::new();
+    for name in flag::referenced_names(dynamic_flag) {
+        joined.push_str(flags.get(name)?);
+        joined.push('\0');
+    }
+    Ok(flag_hash(key, &joined))
+}
+
+/// Hash the flags of the containers in a pod that must be restarted to pick them up
+/// A pod of a single container carries the hash of that container
+pub fn pod_flag_hash(
+    key: &[u8],
+    containers: &[&ContainerSpec],
+    flags: &InstanceFlags,
+) -> Result<Option<String>> {
+    let mut hashes = vec![];
+    for container in containers {
+        if let Some(ref dynamic_flag) = container.dynamic_flag {
+            if requires_restart(dynamic_flag) {
+                hashes.push(container_flag_hash(key, dynamic_flag, flags)?);
+            }
+        }
+    }
+
+    Ok(match hashes.len() {
+        0 => None,
+        1 => hashes.pop(),
+        _ => Some(flag_hash(key, &hashes.join("\0"))),
+    })
+}
+
+/// Check if the flags in the spec differ from the ones that were deployed
+/// Instances deployed before flag hashes were recorded have none and are rotated once
+pub fn flag_changed(key: &[u8], instance: &ChallengeInstance, flags: &InstanceFlags) -> bool {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.flag_hash.as_ref())
+        .is_none_or(|deployed| *deployed != flags_hash(key, flags))
+}
+
+/// Check if containers using this flag must be restarted to pick up a new flag
+/// Env and cookie flags are baked into the pod template and subPath mounts are never refreshed
+pub fn requires_restart(dynamic_flag: &DynamicFlag) -> bool {
+    dynamic_flag.env.is_some()
+        || dynamic_flag.cookie.is_some()
+        || dynamic_flag
+            .content
+            .as_ref()
+            .is_some_and(|c| !c.mount_directory)
+        || dynamic_flag
+            .executable
+            .as_ref()
+            .is_some_and(|e| !e.mount_directory)
+}
+
+/// Update the flag objects of a running instance and roll the affected deployments
+pub async fn rotate_flag(
+    instance: Arc<ChallengeInstance>,
+    challenge: &Challenge,
+    flags: &InstanceFlags,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    let namespace = super::recorded_namespace(&instance)?;
+
+    info!("Rotating flag for instance {}", instance.name_any());
+
+    let hash = flags_hash(&ctx.config.flag_hash_key, flags);
+    let mut rolled = vec![];
+
+    for container in &challenge.spec.containers {
+        if let Some(ref dynamic_flag) = container.dynamic_flag {
+            resources::configmap::update_flag_configmap(dynamic_flag, flags, namespace, &ctx)
+                .await?;
+        }
+    }
+
+    for pod in resources::pod::groups(challenge) {
+        if resources::deployment::rotate_flag(&pod, flags, namespace, &ctx).await? {
+            rolled.push(pod.name.to_string());
+        }
+    }
+
+    let message = if rolled.is_empty() {
+        "Flag updated in place".to_string()
+    } else {
+        format!("Flag updated, restarted {}", rolled.join(", "))
+    };
+
+    let now = DateTime::now();
+    update_status(&instance, &ctx, |status| {
+        status.flag_hash = Some(hash);
+        if let Some(cond) = status
+            .conditions
+            .iter_mut()
+            .find(|c| c.r#type == "FlagRotated")
+        {
+            cond.status = ConditionStatus::True;
+            cond.last_transition_time = Some(now);
+            cond.message = Some(message);
+        } else {
+            status.conditions.push(Condition {
+                r#type: "FlagRotated".to_string(),
+                status: ConditionStatus::True,
+                last_transition_time: Some(now),
+                reason: Some("FlagChanged".to_string()),
+                message: Some(message),
+            });
+        }
+    })
+    .await?;
+
+    Ok(Action::requeue(Duration::from_secs(2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crds::{ContentFlag, EnvFlag};
+    use std::collections::BTreeMap;
+
+    const KEY: &[u8] = b"controller-key";
+
+    #[test]
+    fn test_flag_hash() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            flag_hash(b"Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(flag_hash(KEY, "flag{a}"), flag_hash(KEY, "flag{b}"));
+        // the hash is not a plain SHA-256 of the flag
+        assert_ne!(
+            flag_hash(KEY, "abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(flag_hash(KEY, "flag{a}"), flag_hash(b"other", "flag{a}"));
+    }
+
+    #[test]
+    fn test_flag_changed() {
+        let flags = InstanceFlags {
+            default: "flag{test}".to_string(),
+            named: BTreeMap::new(),
+        };
+        let mut instance = crate::test_fixtures::instance();
+        // deployed before the hash was recorded
+        assert!(flag_changed(KEY, &instance, &flags));
+
+        instance.status = Some(serde_json::from_value(serde_json::json!({})).unwrap());
+        assert!(flag_changed(KEY, &instance, &flags));
+
+        instance.status.as_mut().unwrap().flag_hash = Some(flags_hash(KEY, &flags));
+        assert!(!flag_changed(KEY, &instance, &flags));
+        assert!(flag_changed(b"rotated-key", &instance, &flags));
+    }
+
+    #[test]
+    fn test_requires_restart() {
+        let content = |mount_directory| DynamicFlag {
+            env: None,
+            cookie: None,
+            content: Some(ContentFlag {
+                path: "/flag/flag.txt".to_string(),
+                mode: None,
+                mount_directory,
+                flag_name: None,
+            }),
+            executable: None,
+        };
+        assert!(requires_restart(&content(false)));
+        assert!(!requires_restart(&content(true)));
+
+        let env = DynamicFlag {
+            env: Some(EnvFlag {
+                name: "FLAG".to_string(),
+                flag_name: None,
+            }),
+            cookie: None,
+            content: None,
+            executable: None,
+        };
+        assert!(requires_restart(&env));
+    }
+
+    #[test]
+    fn test_container_flag_hash() {
+        let mut flags = InstanceFlags {
+            default: "flag{user}".to_string(),
+            named: BTreeMap::from([("root".to_string(), "flag{root}".to_string())]),
+        };
+        let root_only = DynamicFlag {
+            env: Some(EnvFlag {
+                name: "FLAG".to_string(),
+                flag_name: Some("root".to_string()),
+            }),
+            cookie: None,
+            content: None,
+            executable: None,
+        };
+
+        let before = container_flag_hash(KEY, &root_only, &flags).unwrap();
+        let all_before = flags_hash(KEY, &flags);
+        flags.default = "flag{rotated}".to_string();
+        assert_eq!(
+            container_flag_hash(KEY, &root_only, &flags).unwrap(),
+            before
+        );
+        assert_ne!(flags_hash(KEY, &flags), all_before);
+    }
+
+    #[test]
+    fn test_pod_flag_hash() {
+        let flags = InstanceFlags {
+            default: "flag{test}".to_string(),
+            named: BTreeMap::new(),
+        };
+        let container = |hostname: &str, dynamic_flag: serde_json::Value| -> ContainerSpec {
+            serde_json::from_value(serde_json::json!({
+                "hostname": hostname,
+                "image": "nginx",
+                "pod": "web",
+                "dynamicFlag": dynamic_flag
+            }))
+            .unwrap()
+        };
+        let app = container("app", serde_json::json!({ "env": { "name": "FLAG" } }));
+        let admin = container(
+            "admin",
+            serde_json::json!({ "content": { "path": "/flag.txt" } }),
+        );
+        let sidecar = container("sidecar", serde_json::json!(null));
+
+        let single = container_flag_hash(KEY, app.dynamic_flag.as_ref().unwrap(), &flags).unwrap();
+        assert_eq!(
+            pod_flag_hash(KEY, &[&app, &sidecar], &flags).unwrap(),
+            Some(single.clone())
+        );
+        assert_ne!(
+            pod_flag_hash(KEY, &[&app, &admin], &flags).unwrap(),
+            Some(single)
+        );
+        assert_eq!(pod_flag_hash(KEY, &[&sidecar], &flags).unwrap(), None);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::crds::{Challenge, ChallengeInstanceClass};
+
+/// Normalize a capability name, "cap_net_admin" and "NET_ADMIN" name the same capability
+pub fn normalize_capability(capability: &str) -> String {
+    let upper = capability.to_uppercase();
+    upper
+        .strip_prefix("CAP_")
+        .map(str::to_string)
+        .unwrap_or(upper)
+}
+
+/// Capabilities requested by the challenge that the class does not allow
+/// Capabilities the class adds to every container are always allowed
+pub fn forbidden_capabilities(
+    challenge: &Challenge,
+    class: &ChallengeInstanceClass,
+) -> Vec<String> {
+    let Some(security) = class.spec.security.as_ref() else {
+        return vec![];
+    };
+    let Some(ref allowed) = security.allowed_capabilities else {
+        return vec![];
+    };
+
+    let allowed: Vec<String> = allowed
+        .iter()
+        .chain(&security.default_capabilities)
+        .map(|c| normalize_capability(c))
+        .collect();
+
+    let mut forbidden: Vec<String> = challenge
+        .spec
+        .containers
+        .iter()
+        .flat_map(|c| &c.additional_capabilities)
+        .map(|c| normalize_capability(c))
+        .filter(|c| !allowed.contains(c))
+        .collect();
+    forbidden.sort();
+    forbidden.dedup();
+    forbidden
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn challenge(capabilities: &[&str]) -> Challenge {
+        crate::test_fixtures::challenge(json!({
+            "containers": [{
+                "hostname": "pwn",
+                "image": "pwn",
+                "additionalCapabilities": capabilities
+            }]
+        }))
+    }
+
+    fn class(security: serde_json::Value) -> ChallengeInstanceClass {
+        crate::test_fixtures::class(json!({ "security": security }))
+    }
+
+    #[test]
+    fn test_forbidden_capabilities() {
+        let class = class(json!({
+            "allowedCapabilities": ["NET_RAW"],
+            "defaultCapabilities": ["CHOWN"]
+        }));
+
+        assert!(forbidden_capabilities(&challenge(&["NET_RAW", "cap_chown"]), &class).is_empty());
+        assert_eq!(
+            forbidden_capabilities(
+                &challenge(&["SYS_ADMIN", "NET_RAW", "CAP_SYS_ADMIN"]),
+                &class
+            ),
+            vec!["SYS_ADMIN".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_no_allowlist() {
+        assert!(forbidden_capabilities(&challenge(&["SYS_ADMIN"]), &class(json!({}))).is_empty());
+        assert!(forbidden_capabilities(&challenge(&["SYS_ADMIN"]), &class(json!(null))).is_empty());
+    }
+}
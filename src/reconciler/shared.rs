@@ -0,0 +1,329 @@
+use crate::{
+    crds::{
+        Challenge, ChallengeInstance, ChallengeInstanceClass, ChallengeInstanceSpec,
+        ChallengeInstanceStatus, ChallengeRef, Condition, ConditionStatus, Phase,
+    },
+    date_time::DateTime,
+    error::Result,
+    utils,
+};
+use kube::{
+    api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PostParams},
+    runtime::controller::Action,
+    Resource, ResourceExt,
+};
+use std::{collections::BTreeMap, time::Duration};
+use tracing::{debug, info};
+
+use super::{update_status, Context, CONTROLLER_OWNER_ID};
+
+/// Label marking the instance serving every owner of a shared challenge
+pub const SHARED_LABEL: &str = "berg.norelect.ch/shared";
+
+/// Label linking a ChallengeInstance to the shared instance it is bound to
+pub const SHARED_INSTANCE_LABEL: &str = "berg.norelect.ch/shared-instance";
+
+/// Check if an instance is the shared instance of a challenge
+/// Labels can be set by anyone creating an instance, so the name and owner must match as well
+pub fn is_shared(instance: &ChallengeInstance) -> bool {
+    let Some(ref class_name) = instance.spec.instance_class else {
+        return false;
+    };
+    let challenge_ns = instance
+        .spec
+        .challenge_ref
+        .namespace
+        .clone()
+        .or_else(|| instance.namespace())
+        .unwrap_or_default();
+
+    instance.labels().contains_key(SHARED_LABEL)
+        && instance.spec.owner_id == CONTROLLER_OWNER_ID
+        && instance.name_any()
+            == shared_instance_name(&challenge_ns, &instance.spec.challenge_ref.name, class_name)
+}
+
+/// Name of the shared instance an instance is bound to, if any
+/// The label is only trusted if the controller recorded the same binding in the status
+pub fn binding_of(instance: &ChallengeInstance) -> Option<&str> {
+    let label = instance.labels().get(SHARED_INSTANCE_LABEL)?;
+    let recorded = instance.status.as_ref()?.shared_instance.as_ref()?;
+    (label == recorded).then_some(label.as_str())
+}
+
+/// Name of the shared instance of a challenge and class
+/// Hashed since the name doubles as a label value, which is limited to 63 characters
+pub fn shared_instance_name(challenge_ns: &str, challenge_name: &str, class_name: &str) -> String {
+    let hash = utils::sha256_hex(&format!(
+        "{}/{}/{}",
+        challenge_ns, challenge_name, class_name
+    ));
+    format!("shared-{}", &hash[..16])
+}
+
+/// Build the shared instance of a challenge
+/// It deploys the static flag of the challenge and only expires with its last binding
+fn build_shared(name: &str, challenge: &Challenge, class_name: &str) -> ChallengeInstance {
+    ChallengeInstance {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            labels: Some(BTreeMap::from([
+                (SHARED_LABEL.to_string(), "true".to_string()),
+                (
+                    "berg.norelect.ch/challenge".to_string(),
+                    challenge.name_any(),
+                ),
+            ])),
+            ..Default::default()
+        },
+        spec: ChallengeInstanceSpec {
+            challenge_ref: ChallengeRef {
+                name: challenge.name_any(),
+                namespace: challenge.namespace(),
+            },
+            owner_id: CONTROLLER_OWNER_ID.to_string(),
+            flag: challenge.spec.flag.clone(),
+            flag_secret_ref: None,
+            flags: BTreeMap::new(),
+            instance_class: Some(class_name.to_string()),
+            timeout: None,
+            termination_reason: None,
+            reset_generation: 0,
+            suspended: false,
+        },
+        status: None,
+    }
+}
+
+/// Bind an instance to the shared instance of its challenge, creating it if needed
+/// Returns the shared instance once it is running
+pub async fn bind(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    class: &ChallengeInstanceClass,
+    ctx: &Context,
+) -> Result<Option<ChallengeInstance>> {
+    let api: Api<ChallengeInstance> = Api::all(ctx.client.clone());
+    let class_name = class.name_any();
+    let name = shared_instance_name(
+        &challenge.namespace().unwrap_or_default(),
+        &challenge.name_any(),
+        &class_name,
+    );
+
+    // record and label the binding first, so the shared instance is never torn down under it
+    if instance
+        .status
+        .as_ref()
+        .and_then(|s| s.shared_instance.as_deref())
+        != Some(name.as_str())
+    {
+        update_status(instance, ctx, |status| {
+            status.shared_instance = Some(name.clone());
+        })
+        .await?;
+    }
+    if instance.labels().get(SHARED_INSTANCE_LABEL) != Some(&name) {
+        let patch = serde_json::json!({
+            "metadata": { "labels": { SHARED_INSTANCE_LABEL: name } }
+        });
+        api.patch(
+            &instance.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await?;
+    }
+
+    let shared = match api.get_opt(&name).await? {
+        Some(shared) => shared,
+        None => {
+            info!(
+                "Creating shared instance {} for challenge {}",
+                name,
+                challenge.name_any()
+            );
+            match api
+                .create(
+                    &PostParams::default(),
+                    &build_shared(&name, challenge, &class_name),
+                )
+                .await
+            {
+                Ok(shared) => shared,
+                Err(kube::Error::Api(ae)) if ae.code == 409 => api.get(&name).await?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    };
+
+    let running = shared.meta().deletion_timestamp.is_none()
+        && shared.status.as_ref().and_then(|s| s.phase.as_ref()) == Some(&Phase::Running);
+    Ok(running.then_some(shared))
+}
+
+/// Report that an instance waits for its shared instance to come up
+pub async fn wait_for_shared(instance: &ChallengeInstance, ctx: &Context) -> Result<Action> {
+    debug!(
+        "Waiting for shared instance of instance {}",
+        instance.name_any()
+    );
+
+    if !instance.status.as_ref().is_some_and(|status| {
+        status
+            .conditions
+            .iter()
+            .any(|c| c.r#type == "Bound" && c.status == ConditionStatus::Unknown)
+    }) {
+        update_status(instance, ctx, |status| {
+            status.conditions.push(Condition {
+                r#type: "Bound".to_string(),
+                status: ConditionStatus::Unknown,
+                last_transition_time: Some(DateTime::now()),
+                reason: Some("WaitingForSharedInstance".to_string()),
+                message: Some("Waiting for the shared instance to be ready".to_string()),
+            });
+        })
+        .await?;
+    }
+
+    Ok(Action::requeue(Duration::from_secs(5)))
+}
+
+/// Point the status of a bound instance at the endpoints of its shared instance
+pub fn bind_status(status: &mut ChallengeInstanceStatus, shared: &ChallengeInstance) {
+    let now = DateTime::now();
+    status.shared_instance = shared.metadata.name.clone();
+    status.services = shared
+        .status
+        .as_ref()
+        .map(|s| s.services.clone())
+        .unwrap_or_default();
+    status.phase = Some(Phase::Running);
+    status.ready_at = Some(now.clone());
+    if let Some(cond) = status.conditions.iter_mut().find(|c| c.r#type == "Bound") {
+        cond.status = ConditionStatus::True;
+        cond.last_transition_time = Some(now);
+        cond.reason = Some("Bound".to_string());
+        cond.message = Some(format!("Bound to shared instance {}", shared.name_any()));
+    } else {
+        status.conditions.push(Condition {
+            r#type: "Bound".to_string(),
+            status: ConditionStatus::True,
+            last_transition_time: Some(now),
+            reason: Some("Bound".to_string()),
+            message: Some(format!("Bound to shared instance {}", shared.name_any())),
+        });
+    }
+}
+
+/// Release the binding of an instance, tearing down the shared instance with its last binding
+pub async fn unbind(instance: &ChallengeInstance, shared_name: &str, ctx: &Context) -> Result<()> {
+    let api: Api<ChallengeInstance> = Api::all(ctx.client.clone());
+    let bindings = api
+        .list(&ListParams::default().labels(&format!("{}={}", SHARED_INSTANCE_LABEL, shared_name)))
+        .await?;
+
+    let remaining = bindings.iter().any(|binding| {
+        binding_of(binding) == Some(shared_name)
+            && binding.uid() != instance.uid()
+            && binding.meta().deletion_timestamp.is_none()
+            && !matches!(
+                binding.status.as_ref().and_then(|s| s.phase.as_ref()),
+                Some(Phase::Terminating | Phase::Terminated | Phase::Failed)
+            )
+    });
+    if remaining {
+        return Ok(());
+    }
+
+    info!(
+        "Last binding of shared instance {} ended, deleting it",
+        shared_name
+    );
+    match api.delete(shared_name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_instance_name() {
+        let name = shared_instance_name("challenges", "web", "default");
+        assert_eq!(name, shared_instance_name("challenges", "web", "default"));
+        assert_ne!(name, shared_instance_name("challenges", "web", "hardened"));
+        assert_ne!(name, shared_instance_name("other", "web", "default"));
+        assert!(name.len() <= 63);
+    }
+
+    #[test]
+    fn test_is_shared() {
+        let challenge = crate::test_fixtures::challenge(serde_json::json!({}));
+        let name = shared_instance_name("challenges", "test", "default");
+        let mut shared = build_shared(&name, &challenge, "default");
+        shared.spec.challenge_ref.namespace = Some("challenges".to_string());
+        assert!(is_shared(&shared));
+
+        // an instance created by a user with the label of a shared instance
+        let mut forged = shared.clone();
+        forged.metadata.name = Some("team-1".to_string());
+        assert!(!is_shared(&forged));
+        let mut forged = shared;
+        forged.spec.owner_id = "team-1".to_string();
+        assert!(!is_shared(&forged));
+    }
+
+    #[test]
+    fn test_binding_of() {
+        let mut instance = crate::test_fixtures::instance();
+        instance.metadata.labels = Some(BTreeMap::from([(
+            SHARED_INSTANCE_LABEL.to_string(),
+            "shared-0123456789abcdef".to_string(),
+        )]));
+        // a label alone is not a binding made by the controller
+        assert_eq!(binding_of(&instance), None);
+
+        instance.status = Some(ChallengeInstanceStatus {
+            shared_instance: Some("shared-0123456789abcdef".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(binding_of(&instance), Some("shared-0123456789abcdef"));
+    }
+
+    #[test]
+    fn test_bind_status() {
+        let mut shared = crate::test_fixtures::instance();
+        shared.metadata.name = Some("shared-0123456789abcdef".to_string());
+        shared.spec.owner_id = CONTROLLER_OWNER_ID.to_string();
+        shared.status = Some(ChallengeInstanceStatus {
+            phase: Some(Phase::Running),
+            services: serde_json::from_value(serde_json::json!([{
+                "name": "web:80",
+                "hostname": "web.example.com",
+                "port": 443,
+                "protocol": "TCP"
+            }]))
+            .unwrap(),
+            ..Default::default()
+        });
+        let mut status = ChallengeInstanceStatus {
+            phase: Some(Phase::Pending),
+            ..Default::default()
+        };
+
+        bind_status(&mut status, &shared);
+
+        assert_eq!(status.phase, Some(Phase::Running));
+        assert_eq!(
+            status.shared_instance.as_deref(),
+            Some("shared-0123456789abcdef")
+        );
+        assert_eq!(status.services.len(), 1);
+        assert!(status.namespace.is_none());
+    }
+}
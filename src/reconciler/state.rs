@@ -1,13 +1,21 @@
-use super::{update_status, Context};
+use super::{
+    event::{self, EventWindow},
+    release, rotation, security, shared, timeout, update_status, warm_pool, Context,
+};
 use crate::{
     crds::{
-        Challenge, ChallengeInstance, ChallengeInstanceClass, Condition, ConditionStatus, Phase,
+        Challenge, ChallengeInstance, ChallengeInstanceClass, Condition, ConditionStatus, Event,
+        Instancing, Phase,
     },
     date_time::DateTime,
     error::{Error, Result},
-    resources, utils,
+    flag, resources,
+};
+use kube::{
+    api::{Api, DeleteParams},
+    runtime::controller::Action,
+    ResourceExt,
 };
-use kube::{runtime::controller::Action, ResourceExt};
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, info};
@@ -16,27 +24,24 @@ use tracing::{debug, info};
 pub async fn reconcile_pending(
     instance: Arc<ChallengeInstance>,
     challenge: Challenge,
-    _class: ChallengeInstanceClass,
+    class: ChallengeInstanceClass,
     ctx: Arc<Context>,
 ) -> Result<Action> {
-    info!("Validating flag for instance {}", instance.name_any());
-
-    // Validate flag if required
-    let requires_flag = challenge
-        .spec
-        .containers
-        .iter()
-        .any(|c| c.dynamic_flag.is_some());
-
-    if requires_flag && instance.spec.flag.is_empty() {
+    // Never deploy capabilities the class does not allow
+    let forbidden = security::forbidden_capabilities(&challenge, &class);
+    if !forbidden.is_empty() {
         update_status(&instance, &ctx, |status| {
             status.phase = Some(Phase::Failed);
             status.conditions.push(Condition {
-                r#type: "FlagValidation".to_string(),
+                r#type: "SecurityPolicy".to_string(),
                 status: ConditionStatus::False,
                 last_transition_time: Some(DateTime::now()),
-                reason: Some("FlagMissing".to_string()),
-                message: Some("Flag required but not provided".to_string()),
+                reason: Some("ForbiddenCapability".to_string()),
+                message: Some(format!(
+                    "Instance class {} does not allow capabilities {}",
+                    class.name_any(),
+                    forbidden.join(", ")
+                )),
             });
         })
         .await?;
@@ -44,20 +49,356 @@ pub async fn reconcile_pending(
         return Ok(Action::await_change());
     }
 
-    // Transition to Creating
-    update_status(&instance, &ctx, |status| {
-        status.phase = Some(Phase::Creating);
+    // Containers grouped into one pod must not collide on names or ports
+    if let Some(message) = resources::pod::groups(&challenge)
+        .iter()
+        .find_map(resources::pod::validate)
+    {
+        return fail_challenge_validation(&instance, &ctx, "InvalidPodGroup", message).await;
+    }
+
+    // Peers are looked up by hostname, a typo would silently isolate the container
+    if let Some((hostname, peer)) = resources::network_policy::unknown_peer(&challenge) {
+        return fail_challenge_validation(
+            &instance,
+            &ctx,
+            "UnknownPeer",
+            format!("Container {} allows unknown peer {}", hostname, peer),
+        )
+        .await;
+    }
+
+    // Spares and shared instances are started on demand and carry no owner flags
+    if warm_pool::is_spare(&instance) || shared::is_shared(&instance) {
+        return start_creating(&instance, &challenge, None, Provisioning::Fresh, &ctx).await;
+    }
+
+    // Refuse to start challenges before they are released
+    let now = chrono::Utc::now();
+    if let Some(release) = release::pending_release(&challenge, &class, now)? {
+        info!(
+            "Challenge for instance {} is hidden until {}",
+            instance.name_any(),
+            release
+        );
+
+        if !instance.status.as_ref().is_some_and(|status| {
+            status
+                .conditions
+                .iter()
+                .any(|c| c.r#type == "Released" && c.status == ConditionStatus::False)
+        }) {
+            // the countdown starts at the release, not while waiting for it
+            let expires_at = timeout::calculate_expiry_from(
+                release,
+                instance
+                    .spec
+                    .timeout
+                    .as_ref()
+                    .unwrap_or(&ctx.config.default_timeout),
+            )?;
+
+            update_status(&instance, &ctx, |status| {
+                status.expires_at = Some(DateTime(expires_at));
+                status.conditions.push(Condition {
+                    r#type: "Released".to_string(),
+                    status: ConditionStatus::False,
+                    last_transition_time: Some(DateTime(now)),
+                    reason: Some("NotYetReleased".to_string()),
+                    message: Some(format!("Challenge is hidden until {}", release)),
+                });
+            })
+            .await?;
+        }
+
+        return Ok(Action::requeue(
+            (release - now).to_std().unwrap_or(Duration::from_secs(1)),
+        ));
+    }
+
+    // Only accept instances while the event of the challenge is open
+    let event = match challenge.spec.event {
+        Some(ref name) => event::fetch_event(name, &ctx).await?,
+        None => None,
+    };
+    if let Some(ref event) = event {
+        let refusal = match event::window(event, now) {
+            EventWindow::Open => None,
+            EventWindow::NotStarted => Some(("EventNotStarted", "Event has not started yet")),
+            EventWindow::Frozen => Some(("EventFrozen", "Event no longer accepts new instances")),
+            EventWindow::Ended => Some(("EventEnded", "Event has ended")),
+        };
+
+        if let Some((reason, message)) = refusal {
+            update_status(&instance, &ctx, |status| {
+                status.phase = Some(Phase::Failed);
+                status.conditions.push(Condition {
+                    r#type: "EventWindow".to_string(),
+                    status: ConditionStatus::False,
+                    last_transition_time: Some(DateTime(now)),
+                    reason: Some(reason.to_string()),
+                    message: Some(message.to_string()),
+                });
+            })
+            .await?;
+
+            return Ok(Action::await_change());
+        }
+    }
+
+    // Instances of shared challenges only bind to the shared instance, their flags are unused
+    if challenge.spec.instancing == Instancing::Shared {
+        return match shared::bind(&instance, &challenge, &class, &ctx).await? {
+            Some(shared) => {
+                start_creating(
+                    &instance,
+                    &challenge,
+                    event.as_ref(),
+                    Provisioning::Bound(&shared),
+                    &ctx,
+                )
+                .await
+            }
+            None => shared::wait_for_shared(&instance, &ctx).await,
+        };
+    }
+
+    info!("Validating flag for instance {}", instance.name_any());
+
+    // Resolve the flags without ever writing them back to the instance
+    let flags = match super::fetch_flags(&instance, &ctx).await {
+        Ok(flags) => flags,
+        Err(Error::FlagSecretNotFound { namespace, name }) => {
+            // the platform may create the secret right after the instance, so keep waiting
+            if !instance.status.as_ref().is_some_and(|status| {
+                status.conditions.iter().any(|c| {
+                    c.r#type == "FlagValidation" && c.reason.as_deref() == Some("FlagSecretMissing")
+                })
+            }) {
+                update_status(&instance, &ctx, |status| {
+                    status.conditions.push(Condition {
+                        r#type: "FlagValidation".to_string(),
+                        status: ConditionStatus::Unknown,
+                        last_transition_time: Some(DateTime::now()),
+                        reason: Some("FlagSecretMissing".to_string()),
+                        message: Some(format!("Waiting for flag secret {}/{}", namespace, name)),
+                    });
+                })
+                .await?;
+            }
+
+            return Ok(Action::requeue(Duration::from_secs(10)));
+        }
+        Err(err @ Error::FlagSecretForbidden { .. }) => {
+            return fail_flag_validation(&instance, &ctx, "FlagSecretForbidden", err.to_string())
+                .await;
+        }
+        Err(err @ Error::FlagSecretKeyMissing { .. }) => {
+            return fail_flag_validation(&instance, &ctx, "FlagSecretKeyMissing", err.to_string())
+                .await;
+        }
+        Err(Error::FlagValidationError(message)) => {
+            return fail_flag_validation(&instance, &ctx, "FlagSecretInvalid", message).await;
+        }
+        Err(err) => return Err(err),
+    };
+
+    // Named flags end up in ConfigMap and volume names
+    if let Some(name) = flags.named.keys().find(|name| !flag::is_valid_name(name)) {
+        return fail_flag_validation(
+            &instance,
+            &ctx,
+            "InvalidFlagName",
+            format!(
+                "Flag name {} is not a DNS label of at most 47 characters",
+                name
+            ),
+        )
+        .await;
+    }
+
+    // Validate every flag a container consumes
+    let referenced = challenge
+        .spec
+        .containers
+        .iter()
+        .filter_map(|c| c.dynamic_flag.as_ref())
+        .flat_map(flag::referenced_names);
+
+    for name in referenced {
+        let flag = flags.get(name).unwrap_or_default();
+        if flag.is_empty() {
+            let message = match name {
+                Some(name) => format!("Flag {} required but not provided", name),
+                None => "Flag required but not provided".to_string(),
+            };
+            return fail_flag_validation(&instance, &ctx, "FlagMissing", message).await;
+        }
+
+        // Catch malformed flags before players see them
+        if let Some(ref flag_format) = challenge.spec.flag_format {
+            if let Err(err) = flag::format::validate(flag_format, flag) {
+                return fail_flag_validation(
+                    &instance,
+                    &ctx,
+                    "FlagFormatMismatch",
+                    err.to_string(),
+                )
+                .await;
+            }
+        }
+    }
+
+    // Take over a running spare instead of provisioning from scratch
+    if let Some(spare) = warm_pool::claim(&instance, &challenge, &class, &ctx).await? {
+        start_creating(
+            &instance,
+            &challenge,
+            event.as_ref(),
+            Provisioning::Claimed(&spare),
+            &ctx,
+        )
+        .await?;
+
+        let instances: Api<ChallengeInstance> = Api::all(ctx.client.clone());
+        match instances
+            .delete(&spare.name_any(), &DeleteParams::default())
+            .await
+        {
+            Ok(_) => {}
+            // the warm pool cleans up claimed spares as well
+            Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        return Ok(Action::requeue(Duration::from_secs(1)));
+    }
+
+    start_creating(
+        &instance,
+        &challenge,
+        event.as_ref(),
+        Provisioning::Fresh,
+        &ctx,
+    )
+    .await
+}
+
+/// Where the resources of an instance leaving Pending come from
+enum Provisioning<'a> {
+    /// Resources are created from scratch
+    Fresh,
+    /// Resources were taken over from a warm pool spare
+    Claimed(&'a ChallengeInstance),
+    /// Resources belong to the shared instance of the challenge
+    Bound(&'a ChallengeInstance),
+}
+
+/// Transition to Creating, or straight to Running when resources already exist
+async fn start_creating(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    event: Option<&Event>,
+    provisioning: Provisioning<'_>,
+    ctx: &Context,
+) -> Result<Action> {
+    // only flags that end up in resources of this instance were validated
+    let validated = match provisioning {
+        Provisioning::Bound(_) => false,
+        _ => !warm_pool::is_spare(instance) && !shared::is_shared(instance),
+    };
+
+    update_status(instance, ctx, |status| {
+        match provisioning {
+            Provisioning::Claimed(spare) => {
+                warm_pool::adopt(status, spare, instance.spec.reset_generation)
+            }
+            Provisioning::Bound(shared) => shared::bind_status(status, shared),
+            Provisioning::Fresh => {
+                status.phase = Some(Phase::Creating);
+                // pin {entropy} paths now so every deployment built later uses the same location
+                status.resolved_flag_paths =
+                    flag::entropy::resolve_paths(challenge, &status.resolved_flag_paths);
+            }
+        }
+        // instances never outlive the event they belong to
+        if let Some(event) = event {
+            status.event = event.metadata.name.clone();
+            if status
+                .expires_at
+                .as_ref()
+                .is_none_or(|expires_at| expires_at.0 > event.spec.ends_at.0)
+            {
+                status.expires_at = Some(event.spec.ends_at.clone());
+            }
+        }
+        if let Some(cond) = status
+            .conditions
+            .iter_mut()
+            .find(|c| c.r#type == "Released")
+        {
+            cond.status = ConditionStatus::True;
+            cond.last_transition_time = Some(DateTime::now());
+            cond.reason = Some("Released".to_string());
+            cond.message = Some("Challenge has been released".to_string());
+        }
+        if validated {
+            status.conditions.push(Condition {
+                r#type: "FlagValidation".to_string(),
+                status: ConditionStatus::True,
+                last_transition_time: Some(DateTime::now()),
+                reason: Some("FlagValid".to_string()),
+                message: Some("Flag validation passed".to_string()),
+            });
+        }
+    })
+    .await?;
+
+    Ok(Action::requeue(Duration::from_secs(1)))
+}
+
+/// Fail an instance whose challenge cannot be deployed
+async fn fail_challenge_validation(
+    instance: &ChallengeInstance,
+    ctx: &Context,
+    reason: &str,
+    message: String,
+) -> Result<Action> {
+    update_status(instance, ctx, |status| {
+        status.phase = Some(Phase::Failed);
+        status.conditions.push(Condition {
+            r#type: "ChallengeValidation".to_string(),
+            status: ConditionStatus::False,
+            last_transition_time: Some(DateTime::now()),
+            reason: Some(reason.to_string()),
+            message: Some(message),
+        });
+    })
+    .await?;
+
+    Ok(Action::await_change())
+}
+
+/// Fail an instance whose flags cannot be used
+async fn fail_flag_validation(
+    instance: &ChallengeInstance,
+    ctx: &Context,
+    reason: &str,
+    message: String,
+) -> Result<Action> {
+    update_status(instance, ctx, |status| {
+        status.phase = Some(Phase::Failed);
         status.conditions.push(Condition {
             r#type: "FlagValidation".to_string(),
-            status: ConditionStatus::True,
+            status: ConditionStatus::False,
             last_transition_time: Some(DateTime::now()),
-            reason: Some("FlagValid".to_string()),
-            message: Some("Flag validation passed".to_string()),
+            reason: Some(reason.to_string()),
+            message: Some(message),
         });
     })
     .await?;
 
-    Ok(Action::requeue(Duration::from_secs(1)))
+    Ok(Action::await_change())
 }
 
 pub async fn reconcile_creating(
@@ -68,15 +409,36 @@ pub async fn reconcile_creating(
 ) -> Result<Action> {
     info!("Creating resources for instance {}", instance.name_any());
 
-    let namespace_name = utils::generate_namespace_name(
-        &ctx.config.namespace_prefix,
-        challenge.metadata.name.as_ref().unwrap(),
-        &instance.spec.owner_id,
-    );
+    let flags = super::fetch_flags(&instance, &ctx).await?;
 
-    resources::namespace::reconcile(&instance, &namespace_name, &ctx).await?;
-    resources::network_policy::reconcile(&instance, &challenge, &namespace_name, &class, &ctx)
-        .await?;
+    let namespace_name = super::namespace_name(&instance, &ctx);
+
+    match resources::namespace::reconcile(&instance, &namespace_name, &class, &ctx).await {
+        Ok(()) => {}
+        Err(Error::NamespaceCollision { name }) => {
+            // wait for the other instance to release the namespace instead of sharing it
+            if !instance.status.as_ref().is_some_and(|status| {
+                status.conditions.iter().any(|c| {
+                    c.r#type == "NamespaceCreated"
+                        && c.reason.as_deref() == Some("NamespaceCollision")
+                })
+            }) {
+                update_status(&instance, &ctx, |status| {
+                    status.conditions.push(Condition {
+                        r#type: "NamespaceCreated".to_string(),
+                        status: ConditionStatus::False,
+                        last_transition_time: Some(DateTime::now()),
+                        reason: Some("NamespaceCollision".to_string()),
+                        message: Some(format!("Namespace {} belongs to another instance", name)),
+                    });
+                })
+                .await?;
+            }
+
+            return Ok(Action::requeue(Duration::from_secs(10)));
+        }
+        Err(err) => return Err(err),
+    }
 
     if let Some(ref image_pull) = class.spec.image_pull {
         for secret in &image_pull.secret_names {
@@ -86,62 +448,129 @@ pub async fn reconcile_creating(
 
     let mut endpoints = Vec::new();
 
-    for container in &challenge.spec.containers {
+    for pod in resources::pod::groups(&challenge) {
         // Services
         endpoints.extend(
             resources::service::reconcile(
                 &class,
                 &instance,
                 &challenge,
-                container,
+                &pod,
                 &namespace_name,
                 &ctx,
             )
             .await?,
         );
 
-        // Gateway API routes
-        endpoints.extend(
-            resources::gateway::create_http_routes(
-                &instance,
-                container,
-                &namespace_name,
-                &class,
-                &ctx,
-            )
-            .await?,
-        );
-        endpoints.extend(
-            resources::gateway::create_tls_routes(
-                &instance,
-                container,
-                &namespace_name,
-                &class,
-                &ctx,
-            )
-            .await?,
-        );
+        for container in &pod.containers {
+            // Gateway API routes, auxiliary containers are never exposed
+            if !container.is_auxiliary() {
+                endpoints.extend(
+                    resources::gateway::create_http_routes(
+                        &instance,
+                        container,
+                        &namespace_name,
+                        &class,
+                        &ctx,
+                    )
+                    .await?,
+                );
+                endpoints.extend(
+                    resources::gateway::create_tls_routes(
+                        &instance,
+                        container,
+                        &namespace_name,
+                        &class,
+                        &ctx,
+                    )
+                    .await?,
+                );
+            }
+        }
+    }
 
-        // ConfigMaps for flags
-        if let Some(ref dynamic_flag) = container.dynamic_flag {
-            resources::configmap::create_flag_configmap(
-                &instance,
-                container,
-                dynamic_flag,
-                &namespace_name,
-                &ctx,
-            )
+    // policies need the route hostnames, and are in place before any pod starts
+    let limitations = resources::network_policy::reconcile(
+        &instance,
+        &challenge,
+        &namespace_name,
+        &class,
+        &endpoints,
+        &ctx,
+    )
+    .await?;
+    // the policy backend of the class may be weaker than the challenge asks for
+    if !limitations.is_empty() {
+        let message = limitations.join("; ");
+        if !instance.status.as_ref().is_some_and(|status| {
+            status
+                .conditions
+                .iter()
+                .any(|c| c.r#type == "NetworkPolicyLimited" && c.message.as_ref() == Some(&message))
+        }) {
+            update_status(&instance, &ctx, |status| {
+                if let Some(cond) = status
+                    .conditions
+                    .iter_mut()
+                    .find(|c| c.r#type == "NetworkPolicyLimited")
+                {
+                    cond.last_transition_time = Some(DateTime::now());
+                    cond.message = Some(message.clone());
+                } else {
+                    status.conditions.push(Condition {
+                        r#type: "NetworkPolicyLimited".to_string(),
+                        status: ConditionStatus::True,
+                        last_transition_time: Some(DateTime::now()),
+                        reason: Some("UnsupportedRules".to_string()),
+                        message: Some(message.clone()),
+                    });
+                }
+            })
             .await?;
         }
+    } else if instance.status.as_ref().is_some_and(|status| {
+        status
+            .conditions
+            .iter()
+            .any(|c| c.r#type == "NetworkPolicyLimited")
+    }) {
+        // the class may have been configured to express every rule since
+        update_status(&instance, &ctx, |status| {
+            status
+                .conditions
+                .retain(|c| c.r#type != "NetworkPolicyLimited");
+        })
+        .await?;
+    }
+
+    for pod in resources::pod::groups(&challenge) {
+        for container in &pod.containers {
+            // ConfigMaps for flags
+            if let Some(ref dynamic_flag) = container.dynamic_flag {
+                resources::configmap::create_flag_configmap(
+                    container,
+                    dynamic_flag,
+                    &flags,
+                    &namespace_name,
+                    &ctx,
+                )
+                .await?;
+            }
+
+            // PersistentVolumeClaims
+            resources::volume::reconcile(&instance, container, &namespace_name, &class, &ctx)
+                .await?;
+        }
 
         // PodDisruptionBudget
-        resources::pdb::reconcile(&instance, container, &namespace_name, &ctx).await?;
+        resources::pdb::reconcile(&instance, pod.name, &namespace_name, &ctx).await?;
 
         // Deployment
         match resources::deployment::reconcile(
             &instance,
             &challenge,
-            container,
+            &pod,
+            &flags,
             &namespace_name,
             &class,
             &endpoints,
@@ -163,8 +592,13 @@ pub async fn reconcile_creating(
     let now = chrono::Utc::now();
     update_status(&instance, &ctx, |status| {
         status.namespace = Some(namespace_name.clone());
+        status.flag_hash = Some(rotation::flags_hash(&ctx.config.flag_hash_key, &flags));
+        // fresh resources need no reset
+        status.observed_reset_generation = instance.spec.reset_generation;
         // move on to next phase
         status.phase = Some(Phase::Starting);
+        // replaces a collision reported while waiting for the namespace
+        status.conditions.retain(|c| c.r#type != "NamespaceCreated");
         status.conditions.extend([
             Condition {
                 r#type: "NamespaceCreated".to_string(),
@@ -221,17 +655,29 @@ pub async fn reconcile_starting(
                 reason: Some("AllReady".to_string()),
                 message: Some("All pods are ready".to_string()),
             });
+            if let Some(cond) = status
+                .conditions
+                .iter_mut()
+                .find(|c| c.r#type == "QuotaExceeded" && c.status == ConditionStatus::True)
+            {
+                cond.status = ConditionStatus::False;
+                cond.last_transition_time = Some(DateTime(now));
+                cond.reason = Some("WithinQuota".to_string());
+                cond.message = Some("All pods fit into the resource quota".to_string());
+            }
         })
         .await?;
 
-        // Requeue at expiration time
-        let expires_at_dt = instance
+        // Requeue at expiration time, shared instances never expire on their own
+        let duration = instance
             .status
             .as_ref()
             .and_then(|s| s.expires_at.as_ref())
-            .expect("expiresAt should be set");
-        let duration = (expires_at_dt.0 - chrono::Utc::now())
-            .to_std()
+            .map(|expires_at_dt| {
+                (expires_at_dt.0 - chrono::Utc::now())
+                    .to_std()
+                    .unwrap_or(Duration::from_secs(3600))
+            })
             .unwrap_or(Duration::from_secs(3600));
 
         Ok(Action::requeue(duration))
@@ -242,6 +688,42 @@ pub async fn reconcile_starting(
         );
 
         let now = chrono::Utc::now();
+
+        // Pods rejected by the ResourceQuota of the class never become ready on their own
+        let quota_failures = resources::deployment::quota_failures(&ctx.client, namespace).await?;
+        if let Some(message) = quota_failures.first() {
+            if !instance.status.as_ref().is_some_and(|status| {
+                status.conditions.iter().any(|c| {
+                    c.r#type == "QuotaExceeded"
+                        && c.status == ConditionStatus::True
+                        && c.message.as_ref() == Some(message)
+                })
+            }) {
+                update_status(&instance, &ctx, |status| {
+                    if let Some(cond) = status
+                        .conditions
+                        .iter_mut()
+                        .find(|c| c.r#type == "QuotaExceeded")
+                    {
+                        cond.status = ConditionStatus::True;
+                        cond.last_transition_time = Some(DateTime(now));
+                        cond.reason = Some("ExceededQuota".to_string());
+                        cond.message = Some(message.clone());
+                    } else {
+                        status.conditions.push(Condition {
+                            r#type: "QuotaExceeded".to_string(),
+                            status: ConditionStatus::True,
+                            last_transition_time: Some(DateTime(now)),
+                            reason: Some("ExceededQuota".to_string()),
+                            message: Some(message.clone()),
+                        });
+                    }
+                })
+                .await?;
+            }
+
+            return Ok(Action::requeue(Duration::from_secs(10)));
+        }
         if !instance
             .status
             .as_ref()
@@ -290,13 +772,11 @@ pub async fn reconcile_running(
         return super::timeout::terminate_expired(instance, ctx).await;
     }
 
-    let expires_at_dt = instance
+    let duration = instance
         .status
         .as_ref()
         .and_then(|s| s.expires_at.as_ref())
-        .expect("expiresAt should be set");
-    let duration = (expires_at_dt.0 - chrono::Utc::now())
-        .to_std()
+        .and_then(|expires_at_dt| (expires_at_dt.0 - chrono::Utc::now()).to_std().ok())
         .unwrap_or(Duration::from_secs(600))
         // requeue every 10 minutes just in case
         .min(Duration::from_secs(600));
@@ -0,0 +1,123 @@
+use crate::{
+    crds::{ChallengeInstance, Condition, ConditionStatus, Phase},
+    date_time::DateTime,
+    error::Result,
+    resources,
+};
+use chrono::Utc;
+use kube::{runtime::controller::Action, ResourceExt};
+use std::{sync::Arc, time::Duration};
+use tracing::info;
+
+use super::{update_status, Context};
+
+/// Seconds left until an instance expires, never negative
+pub fn remaining_seconds(instance: &ChallengeInstance, now: chrono::DateTime<Utc>) -> i64 {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.expires_at.as_ref())
+        .map(|expires_at| (expires_at.0 - now).num_seconds().max(0))
+        .unwrap_or_default()
+}
+
+/// Scale an instance to zero and freeze its expiry timer
+pub async fn suspend(instance: Arc<ChallengeInstance>, ctx: Arc<Context>) -> Result<Action> {
+    let namespace = super::recorded_namespace(&instance)?;
+
+    info!("Suspending instance {}", instance.name_any());
+    resources::deployment::scale_all(namespace, 0, &ctx).await?;
+
+    let now = Utc::now();
+    let remaining = remaining_seconds(&instance, now);
+    update_status(&instance, &ctx, |status| {
+        status.phase = Some(Phase::Suspended);
+        status.remaining_seconds = Some(remaining);
+        status.expires_at = None;
+        status.conditions.retain(|c| c.r#type != "PodsReady");
+        set_suspended_condition(&mut status.conditions, ConditionStatus::True, now);
+    })
+    .await?;
+
+    Ok(Action::await_change())
+}
+
+/// Suspended phase - resume once the instance is no longer suspended
+pub async fn reconcile_suspended(
+    instance: Arc<ChallengeInstance>,
+    ctx: Arc<Context>,
+) -> Result<Action> {
+    if instance.spec.suspended {
+        return Ok(Action::await_change());
+    }
+
+    let namespace = super::recorded_namespace(&instance)?;
+
+    info!("Resuming instance {}", instance.name_any());
+    resources::deployment::scale_all(namespace, 1, &ctx).await?;
+
+    let now = Utc::now();
+    let remaining = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.remaining_seconds)
+        .unwrap_or_default();
+    update_status(&instance, &ctx, |status| {
+        // wait for the pods to come back before reporting the instance as running
+        status.phase = Some(Phase::Starting);
+        status.expires_at = Some(DateTime(now + chrono::Duration::seconds(remaining)));
+        status.remaining_seconds = None;
+        set_suspended_condition(&mut status.conditions, ConditionStatus::False, now);
+    })
+    .await?;
+
+    Ok(Action::requeue(Duration::from_secs(2)))
+}
+
+fn set_suspended_condition(
+    conditions: &mut Vec<Condition>,
+    status: ConditionStatus,
+    now: chrono::DateTime<Utc>,
+) {
+    let (reason, message) = match status {
+        ConditionStatus::True => ("Suspended", "Instance is suspended"),
+        _ => ("Resumed", "Instance was resumed"),
+    };
+
+    if let Some(cond) = conditions.iter_mut().find(|c| c.r#type == "Suspended") {
+        cond.status = status;
+        cond.last_transition_time = Some(DateTime(now));
+        cond.reason = Some(reason.to_string());
+        cond.message = Some(message.to_string());
+    } else {
+        conditions.push(Condition {
+            r#type: "Suspended".to_string(),
+            status,
+            last_transition_time: Some(DateTime(now)),
+            reason: Some(reason.to_string()),
+            message: Some(message.to_string()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining_seconds() {
+        let now = Utc::now();
+        let mut instance = crate::test_fixtures::instance();
+        assert_eq!(remaining_seconds(&instance, now), 0);
+
+        instance.status = Some(crate::crds::ChallengeInstanceStatus {
+            expires_at: Some(DateTime(now + chrono::Duration::minutes(30))),
+            ..Default::default()
+        });
+        assert_eq!(remaining_seconds(&instance, now), 1800);
+
+        instance.status.as_mut().unwrap().expires_at =
+            Some(DateTime(now - chrono::Duration::minutes(1)));
+        assert_eq!(remaining_seconds(&instance, now), 0);
+    }
+}
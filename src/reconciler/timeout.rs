@@ -25,8 +25,16 @@ pub fn is_expired(instance: &ChallengeInstance) -> bool {
 
 /// Calculate expiry time from a timeout string like "2h", "30m", "1h30m"
 pub fn calculate_expiry(timeout_str: &str) -> Result<chrono::DateTime<Utc>> {
+    calculate_expiry_from(Utc::now(), timeout_str)
+}
+
+/// Calculate expiry time for an instance starting at the given time
+pub fn calculate_expiry_from(
+    start: chrono::DateTime<Utc>,
+    timeout_str: &str,
+) -> Result<chrono::DateTime<Utc>> {
     let duration = parse_timeout(timeout_str)?;
-    let expiry = Utc::now() + duration;
+    let expiry = start + duration;
     Ok(expiry)
 }
 
@@ -93,12 +101,21 @@ pub async fn terminate_expired(
     info!("Instance {} has expired, terminating", instance.name_any());
     ctx.metrics.record_timeout();
 
+    terminate(instance, ctx, TerminationReason::Timeout).await
+}
+
+/// Record the termination reason and delete the instance
+pub async fn terminate(
+    instance: Arc<ChallengeInstance>,
+    ctx: Arc<Context>,
+    reason: TerminationReason,
+) -> Result<Action> {
     let api: Api<ChallengeInstance> = Api::all(ctx.client.clone());
 
     // Set termination reason and delete
     let patch = serde_json::json!({
         "spec": {
-            "terminationReason": TerminationReason::Timeout
+            "terminationReason": reason
         }
     });
 
@@ -0,0 +1,661 @@
+use crate::{
+    crds::{
+        Challenge, ChallengeInstance, ChallengeInstanceClass, ChallengeInstanceSpec,
+        ChallengeInstanceStatus, ChallengeRef, CiliumNetworkPolicy, Condition, ConditionStatus,
+        HTTPRoute, Phase, TLSRoute, WarmPool, WarmPoolStatus,
+    },
+    date_time::DateTime,
+    error::{Error, Result},
+    flag,
+    resources::labels,
+};
+use k8s_openapi::{
+    api::{
+        apps::v1::Deployment,
+        core::v1::{LimitRange, Namespace, PersistentVolumeClaim, ResourceQuota, Service},
+        networking::v1::NetworkPolicy,
+        policy::v1::PodDisruptionBudget,
+    },
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+};
+use kube::{
+    api::{Api, DeleteParams, ListParams, ObjectMeta, Patch, PatchParams, PostParams},
+    runtime::controller::Action,
+    Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+
+use super::{
+    event::{self, EventWindow},
+    Context, CONTROLLER_OWNER_ID,
+};
+
+/// Label linking a spare instance to its warm pool
+pub const WARM_POOL_LABEL: &str = "berg.norelect.ch/warm-pool";
+
+/// Label marking a spare that has been taken over, value is the UID of the claiming instance
+pub const CLAIMED_BY_LABEL: &str = "berg.norelect.ch/claimed-by";
+
+/// Check if an instance is a spare kept by a warm pool
+/// Labels can be set by anyone creating an instance, so the spare must also be controlled by a
+/// warm pool and keep the controller as its owner
+pub fn is_spare(instance: &ChallengeInstance) -> bool {
+    let controlled_by_pool = instance.owner_references().iter().any(|o| {
+        o.controller == Some(true)
+            && o.kind == WarmPool::kind(&())
+            && o.api_version == WarmPool::api_version(&())
+    });
+
+    controlled_by_pool
+        && instance.spec.owner_id == CONTROLLER_OWNER_ID
+        && instance.labels().contains_key(WARM_POOL_LABEL)
+}
+
+/// Check if a spare has been taken over by another instance
+/// Its namespace now belongs to the claiming instance and must survive its deletion
+pub fn is_claimed(instance: &ChallengeInstance) -> bool {
+    is_spare(instance) && instance.labels().contains_key(CLAIMED_BY_LABEL)
+}
+
+/// Check if a spare has been claimed by the given instance
+fn is_claimed_by(spare: &ChallengeInstance, instance: &ChallengeInstance) -> bool {
+    instance
+        .uid()
+        .is_some_and(|uid| spare.labels().get(CLAIMED_BY_LABEL) == Some(&uid))
+}
+
+/// Check if the claiming instance of a spare has yet to take over its namespace
+/// The spare must be kept until then, it is the only record of the namespace handed over
+fn hand_over_pending(spare: &ChallengeInstance, instances: &[ChallengeInstance]) -> bool {
+    let Some(uid) = spare.labels().get(CLAIMED_BY_LABEL) else {
+        return false;
+    };
+    let namespace = spare.status.as_ref().and_then(|s| s.namespace.as_ref());
+
+    instances.iter().any(|instance| {
+        instance.uid().as_ref() == Some(uid)
+            && instance.meta().deletion_timestamp.is_none()
+            && instance.status.as_ref().and_then(|s| s.namespace.as_ref()) != namespace
+    })
+}
+
+/// Check if a spare can be claimed right now
+fn is_available(instance: &ChallengeInstance) -> bool {
+    instance.meta().deletion_timestamp.is_none()
+        && !is_claimed(instance)
+        && instance.status.as_ref().and_then(|s| s.phase.as_ref()) == Some(&Phase::Running)
+}
+
+/// Build a spare instance for a warm pool
+/// Spares carry a throwaway flag for every flag the challenge consumes, the claiming
+/// instance rotates in its own flags afterwards
+fn build_spare(pool: &WarmPool, challenge: &Challenge, challenge_ns: &str) -> ChallengeInstance {
+    let placeholder = || format!("spare-{}", uuid::Uuid::new_v4());
+
+    let mut named = BTreeMap::new();
+    for name in challenge
+        .spec
+        .containers
+        .iter()
+        .filter_map(|c| c.dynamic_flag.as_ref())
+        .flat_map(flag::referenced_names)
+        .flatten()
+    {
+        named.insert(name.to_string(), placeholder());
+    }
+
+    ChallengeInstance {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{}-", pool.name_any())),
+            labels: Some(BTreeMap::from([(
+                WARM_POOL_LABEL.to_string(),
+                pool.name_any(),
+            )])),
+            owner_references: Some(vec![pool.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        spec: ChallengeInstanceSpec {
+            challenge_ref: ChallengeRef {
+                name: pool.spec.challenge_ref.name.clone(),
+                namespace: Some(challenge_ns.to_string()),
+            },
+            owner_id: CONTROLLER_OWNER_ID.to_string(),
+            flag: placeholder(),
+            flag_secret_ref: None,
+            flags: named,
+            instance_class: pool.spec.instance_class.clone(),
+            timeout: Some(pool.spec.spare_timeout.clone()),
+            termination_reason: None,
+            reset_generation: 0,
+            suspended: false,
+        },
+        status: None,
+    }
+}
+
+/// Keep the configured number of spares around for a warm pool
+pub async fn reconcile_pool(pool: Arc<WarmPool>, ctx: Arc<Context>) -> Result<Action> {
+    let name = pool.name_any();
+    debug!("Reconciling WarmPool {}", name);
+    ctx.metrics.record_reconcile();
+
+    let challenge_ns = pool
+        .spec
+        .challenge_ref
+        .namespace
+        .as_deref()
+        .unwrap_or(ctx.client.default_namespace());
+    let challenges: Api<Challenge> = Api::namespaced(ctx.client.clone(), challenge_ns);
+    let challenge = challenges
+        .get(&pool.spec.challenge_ref.name)
+        .await
+        .map_err(|e| match e {
+            kube::Error::Api(ae) if ae.code == 404 => Error::ChallengeNotFound {
+                namespace: challenge_ns.to_string(),
+                name: pool.spec.challenge_ref.name.clone(),
+            },
+            e => Error::from(e),
+        })?;
+
+    let instances: Api<ChallengeInstance> = Api::all(ctx.client.clone());
+    let spares = instances
+        .list(&ListParams::default().labels(&format!("{}={}", WARM_POOL_LABEL, name)))
+        .await?;
+
+    // instances that may still be taking over a claimed spare
+    let claimers = if spares.iter().any(is_claimed) {
+        instances.list(&ListParams::default()).await?.items
+    } else {
+        vec![]
+    };
+
+    // Claimed spares are left over from a claim, dead spares are replaced
+    let mut live = vec![];
+    for spare in spares {
+        if spare.meta().deletion_timestamp.is_some() || hand_over_pending(&spare, &claimers) {
+            continue;
+        }
+        let dead = matches!(
+            spare.status.as_ref().and_then(|s| s.phase.as_ref()),
+            Some(Phase::Failed | Phase::Terminated)
+        );
+        if is_claimed(&spare) || dead {
+            instances
+                .delete(&spare.name_any(), &DeleteParams::default())
+                .await?;
+            continue;
+        }
+        live.push(spare);
+    }
+
+    // Spares of an event that is over would be terminated right away
+    let ended = match challenge.spec.event {
+        Some(ref event_name) => event::fetch_event(event_name, &ctx)
+            .await?
+            .is_some_and(|e| event::window(&e, chrono::Utc::now()) == EventWindow::Ended),
+        None => false,
+    };
+    let size = if ended {
+        0
+    } else {
+        pool.spec.size.max(0) as usize
+    };
+
+    if live.len() < size {
+        for _ in live.len()..size {
+            let spare = instances
+                .create(
+                    &PostParams::default(),
+                    &build_spare(&pool, &challenge, challenge_ns),
+                )
+                .await?;
+            info!("Created spare {} for warm pool {}", spare.name_any(), name);
+        }
+    } else if live.len() > size {
+        // drop spares that are still starting first
+        live.sort_by_key(is_available);
+        for spare in live.drain(..live.len() - size) {
+            instances
+                .delete(&spare.name_any(), &DeleteParams::default())
+                .await?;
+            info!(
+                "Deleted surplus spare {} of warm pool {}",
+                spare.name_any(),
+                name
+            );
+        }
+    }
+
+    let status = WarmPoolStatus {
+        ready: live.iter().filter(|spare| is_available(spare)).count() as i32,
+        total: live.len().max(size) as i32,
+    };
+    let pools: Api<WarmPool> = Api::all(ctx.client.clone());
+    pools
+        .patch_status(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(&serde_json::json!({ "status": status })),
+        )
+        .await?;
+
+    Ok(Action::requeue(Duration::from_secs(60)))
+}
+
+/// Error handling for warm pool reconciliation
+pub fn error_policy(_pool: Arc<WarmPool>, error: &Error, ctx: Arc<Context>) -> Action {
+    warn!("[*] Warm pool reconciliation error: {:?}", error);
+    ctx.metrics.record_error();
+
+    if error.is_retryable() {
+        Action::requeue(Duration::from_secs(10))
+    } else {
+        Action::requeue(Duration::from_secs(300))
+    }
+}
+
+/// Take over a running spare from a warm pool matching the challenge and class
+/// The spare's namespace and resources are handed to the instance, the spare itself is
+/// marked as claimed and deleted by the caller once the instance status points at them
+pub async fn claim(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    class: &ChallengeInstanceClass,
+    ctx: &Context,
+) -> Result<Option<ChallengeInstance>> {
+    let challenge_ns = challenge.namespace().unwrap_or_default();
+    let class_name = class.name_any();
+
+    let pools: Api<WarmPool> = Api::all(ctx.client.clone());
+    let instances: Api<ChallengeInstance> = Api::all(ctx.client.clone());
+
+    let mut candidates = vec![];
+    for pool in pools.list(&ListParams::default()).await? {
+        let pool_ns = pool
+            .spec
+            .challenge_ref
+            .namespace
+            .as_deref()
+            .unwrap_or(ctx.client.default_namespace());
+        let pool_class = pool
+            .spec
+            .instance_class
+            .as_deref()
+            .unwrap_or(&ctx.config.default_instance_class);
+        if pool.spec.challenge_ref.name != challenge.name_any()
+            || pool_ns != challenge_ns
+            || pool_class != class_name
+        {
+            continue;
+        }
+
+        candidates.extend(
+            instances
+                .list(&ListParams::default().labels(&format!(
+                    "{}={}",
+                    WARM_POOL_LABEL,
+                    pool.name_any()
+                )))
+                .await?,
+        );
+    }
+
+    // resume a hand-over that failed after the spare was marked as claimed by this instance,
+    // claiming another spare would leave the namespace of the first one behind
+    if let Some(spare) = candidates.iter().find(|s| is_claimed_by(s, instance)) {
+        hand_over(instance, spare, ctx).await?;
+        info!(
+            "Instance {} resumed taking over spare {}",
+            instance.name_any(),
+            spare.name_any()
+        );
+        return Ok(Some(spare.clone()));
+    }
+
+    for spare in candidates.into_iter().filter(is_available) {
+        // the resourceVersion precondition makes concurrent claims of one spare fail
+        let patch = serde_json::json!({
+            "metadata": {
+                "resourceVersion": spare.resource_version(),
+                "labels": { CLAIMED_BY_LABEL: instance.uid() }
+            }
+        });
+        match instances
+            .patch(
+                &spare.name_any(),
+                &PatchParams::default(),
+                &Patch::Merge(&patch),
+            )
+            .await
+        {
+            Ok(_) => {}
+            Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                debug!("Spare {} was claimed concurrently", spare.name_any());
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        hand_over(instance, &spare, ctx).await?;
+        info!(
+            "Instance {} claimed spare {}",
+            instance.name_any(),
+            spare.name_any()
+        );
+        return Ok(Some(spare));
+    }
+
+    Ok(None)
+}
+
+/// Move ownership of a spare's namespace and resources to the claiming instance
+async fn hand_over(
+    instance: &ChallengeInstance,
+    spare: &ChallengeInstance,
+    ctx: &Context,
+) -> Result<()> {
+    // check everything up front, so ownership is never left half moved
+    let namespace = super::recorded_namespace(spare)?;
+    let spare_uid = spare.uid().unwrap_or_default();
+    let owner = instance
+        .controller_owner_ref(&())
+        .ok_or_else(|| Error::UidNotAssigned {
+            name: instance.name_any(),
+        })?;
+    let claimed_labels = labels::claimed_labels(instance, ctx);
+
+    let namespaces: Api<Namespace> = Api::all(ctx.client.clone());
+    namespaces
+        .patch(
+            namespace,
+            &PatchParams::default(),
+            &Patch::Merge(&serde_json::json!({
+                "metadata": {
+                    "ownerReferences": [owner],
+                    "labels": claimed_labels
+                }
+            })),
+        )
+        .await?;
+
+    let client = ctx.client.clone();
+    reown::<Deployment>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<Service>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<PodDisruptionBudget>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<CiliumNetworkPolicy>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<NetworkPolicy>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<HTTPRoute>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<ResourceQuota>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<LimitRange>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<PersistentVolumeClaim>(
+        Api::namespaced(client.clone(), namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+    reown::<TLSRoute>(
+        Api::namespaced(client, namespace),
+        &spare_uid,
+        &owner,
+        &claimed_labels,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Point the owner reference of every object owned by the spare at the claiming instance
+/// Pod templates are left alone so the hand-over does not restart anything
+async fn reown<K>(
+    api: Api<K>,
+    spare_uid: &str,
+    owner: &OwnerReference,
+    claimed_labels: &BTreeMap<String, String>,
+) -> Result<()>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+{
+    let objects = match api.list(&ListParams::default()).await {
+        Ok(objects) => objects,
+        // kinds the cluster does not serve, e.g. CiliumNetworkPolicies without Cilium
+        Err(kube::Error::Api(ae)) if ae.code == 404 => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for object in objects {
+        if !object.owner_references().iter().any(|r| r.uid == spare_uid) {
+            continue;
+        }
+
+        // only relabel the labels the object already carries
+        let relabel: BTreeMap<_, _> = claimed_labels
+            .iter()
+            .filter(|(key, _)| object.labels().contains_key(*key))
+            .collect();
+        api.patch(
+            &object.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&serde_json::json!({
+                "metadata": {
+                    "ownerReferences": [owner],
+                    "labels": relabel
+                }
+            })),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Adopt the state of a claimed spare into the status of the claiming instance
+/// The flag hash of the spare is kept so the next reconcile rotates in the instance's flags,
+/// the instance stays Starting until the pods serving the spare's placeholder flags are gone
+pub fn adopt(
+    status: &mut ChallengeInstanceStatus,
+    spare: &ChallengeInstance,
+    reset_generation: i64,
+) {
+    let Some(ref spare_status) = spare.status else {
+        return;
+    };
+
+    let now = DateTime::now();
+    status.instance_id = spare_status.instance_id.clone();
+    status.namespace = spare_status.namespace.clone();
+    status.services = spare_status.services.clone();
+    status.resolved_flag_paths = spare_status.resolved_flag_paths.clone();
+    status.flag_hash = spare_status.flag_hash.clone();
+    status.observed_reset_generation = reset_generation;
+    status.phase = Some(Phase::Starting);
+    status.conditions.push(Condition {
+        r#type: "Claimed".to_string(),
+        status: ConditionStatus::True,
+        last_transition_time: Some(now),
+        reason: Some("WarmPool".to_string()),
+        message: Some(format!("Claimed spare {}", spare.name_any())),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spare(labels: serde_json::Value, phase: Phase) -> ChallengeInstance {
+        let mut spare = crate::test_fixtures::instance();
+        spare.metadata.name = Some("web-pool-abcde".to_string());
+        spare.metadata.labels = serde_json::from_value(labels).unwrap();
+        spare.metadata.owner_references = Some(vec![OwnerReference {
+            api_version: WarmPool::api_version(&()).to_string(),
+            kind: WarmPool::kind(&()).to_string(),
+            name: "web-pool".to_string(),
+            uid: "0b1c2d3e-0000-0000-0000-000000000000".to_string(),
+            controller: Some(true),
+            ..Default::default()
+        }]);
+        spare.spec.owner_id = CONTROLLER_OWNER_ID.to_string();
+        spare.status = Some(ChallengeInstanceStatus {
+            instance_id: Some("f0e1d2c3-b4a5-9687-7869-5a4b3c2d1e0f".to_string()),
+            phase: Some(phase),
+            namespace: Some("ci-web-f0e1d2c3-b4a5-9687-7869-5a4b3c2d1e0f".to_string()),
+            resolved_flag_paths: BTreeMap::from([(
+                "web/content".to_string(),
+                "/flag-1a2b3c/flag.txt".to_string(),
+            )]),
+            flag_hash: Some("deadbeef".to_string()),
+            ..Default::default()
+        });
+        spare
+    }
+
+    #[test]
+    fn test_is_available() {
+        assert!(is_available(&spare(
+            json!({ WARM_POOL_LABEL: "web-pool" }),
+            Phase::Running
+        )));
+        assert!(!is_available(&spare(
+            json!({ WARM_POOL_LABEL: "web-pool" }),
+            Phase::Starting
+        )));
+        assert!(!is_available(&spare(
+            json!({ WARM_POOL_LABEL: "web-pool", CLAIMED_BY_LABEL: "team-1" }),
+            Phase::Running
+        )));
+    }
+
+    #[test]
+    fn test_labels_alone_make_no_spare() {
+        let spare = spare(
+            json!({ WARM_POOL_LABEL: "web-pool", CLAIMED_BY_LABEL: "team-1" }),
+            Phase::Running,
+        );
+        assert!(is_spare(&spare));
+        assert!(is_claimed(&spare));
+
+        // an instance created by a user with the labels of a claimed spare
+        let mut forged = spare.clone();
+        forged.metadata.owner_references = None;
+        assert!(!is_spare(&forged));
+        assert!(!is_claimed(&forged));
+
+        // a spare keeps the controller as its owner, even once claimed
+        let mut owned = spare;
+        owned.spec.owner_id = "team-1".to_string();
+        assert!(!is_spare(&owned));
+        assert!(!is_claimed(&owned));
+    }
+
+    #[test]
+    fn test_hand_over_pending() {
+        let claimer = |namespace: Option<&str>| -> ChallengeInstance {
+            let mut claimer = crate::test_fixtures::instance();
+            claimer.status = Some(ChallengeInstanceStatus {
+                namespace: namespace.map(str::to_string),
+                ..Default::default()
+            });
+            claimer
+        };
+        let spare = spare(
+            json!({
+                WARM_POOL_LABEL: "web-pool",
+                CLAIMED_BY_LABEL: "8f5c1f8e-0000-0000-0000-000000000000"
+            }),
+            Phase::Running,
+        );
+
+        assert!(is_claimed_by(&spare, &claimer(None)));
+        // the claimer has not recorded the namespace yet, so the spare must stay
+        assert!(hand_over_pending(&spare, &[claimer(None)]));
+        assert!(!hand_over_pending(
+            &spare,
+            &[claimer(Some("ci-web-f0e1d2c3-b4a5-9687-7869-5a4b3c2d1e0f"))]
+        ));
+        // the namespace is garbage collected with a claimer that is gone
+        assert!(!hand_over_pending(&spare, &[]));
+    }
+
+    #[test]
+    fn test_adopt() {
+        let spare = spare(json!({ WARM_POOL_LABEL: "web-pool" }), Phase::Running);
+        let mut status = ChallengeInstanceStatus {
+            instance_id: Some("own-id".to_string()),
+            phase: Some(Phase::Pending),
+            ..Default::default()
+        };
+
+        adopt(&mut status, &spare, 2);
+
+        // the spare's placeholder flags are live until the rotation rolled out
+        assert_eq!(status.phase, Some(Phase::Starting));
+        assert!(status.ready_at.is_none());
+        assert_eq!(
+            status.instance_id.as_deref(),
+            Some("f0e1d2c3-b4a5-9687-7869-5a4b3c2d1e0f")
+        );
+        assert_eq!(
+            status.namespace.as_deref(),
+            Some("ci-web-f0e1d2c3-b4a5-9687-7869-5a4b3c2d1e0f")
+        );
+        assert_eq!(status.flag_hash.as_deref(), Some("deadbeef"));
+        assert_eq!(status.observed_reset_generation, 2);
+        assert_eq!(
+            status
+                .resolved_flag_paths
+                .get("web/content")
+                .map(String::as_str),
+            Some("/flag-1a2b3c/flag.txt")
+        );
+    }
+}
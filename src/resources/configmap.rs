@@ -1,49 +1,26 @@
 use crate::{
-    crds::{ChallengeInstance, ContainerSpec, DynamicFlag},
+    crds::{ContainerSpec, DynamicFlag},
     error::{Error, Result},
+    flag::{self, InstanceFlags},
     reconciler::Context,
 };
 use k8s_openapi::api::core::v1::ConfigMap;
-use kube::api::{Api, PostParams};
+use kube::api::{Api, Patch, PatchParams, PostParams};
 use std::collections::BTreeMap;
 use tracing::{debug, info};
 
 pub async fn create_flag_configmap(
-    instance: &ChallengeInstance,
     _container: &ContainerSpec,
     dynamic_flag: &DynamicFlag,
+    flags: &InstanceFlags,
     namespace: &str,
     ctx: &Context,
 ) -> Result<()> {
     let api: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), namespace);
 
-    if let Some(ref _content) = dynamic_flag.content {
-        let flag_content = format!("{}\n", instance.spec.flag);
-
-        let mut data = BTreeMap::new();
-        data.insert("content".to_string(), flag_content);
-
-        let cm = ConfigMap {
-            metadata: kube::api::ObjectMeta {
-                name: Some("flag-content".to_string()),
-                namespace: Some(namespace.to_string()),
-                labels: Some({
-                    let mut labels = BTreeMap::new();
-                    labels.insert(
-                        "app.kubernetes.io/managed-by".to_string(),
-                        "berg".to_string(),
-                    );
-                    labels.insert(
-                        "app.kubernetes.io/component".to_string(),
-                        "flag-content".to_string(),
-                    );
-                    labels
-                }),
-                ..Default::default()
-            },
-            data: Some(data),
-            ..Default::default()
-        };
+    if let Some(ref content) = dynamic_flag.content {
+        let flag = flags.get(content.flag_name.as_deref())?;
+        let cm = build_content_configmap(content.flag_name.as_deref(), flag, namespace);
 
         match api.create(&PostParams::default(), &cm).await {
             Ok(_) => info!("Created flag content ConfigMap in {}", namespace),
@@ -55,37 +32,9 @@ pub async fn create_flag_configmap(
     }
 
     // Create ConfigMap for executable flag
-    if let Some(ref _executable) = dynamic_flag.executable {
-        // Generate minimal ELF executable that outputs the flag
-        let elf_binary = crate::flag::executable::generate_elf_executable(&instance.spec.flag)?;
-
-        let mut binary_data = BTreeMap::new();
-        binary_data.insert(
-            "executable".to_string(),
-            k8s_openapi::ByteString(elf_binary),
-        );
-
-        let cm = ConfigMap {
-            metadata: kube::api::ObjectMeta {
-                name: Some("flag-executable".to_string()),
-                namespace: Some(namespace.to_string()),
-                labels: Some({
-                    let mut labels = BTreeMap::new();
-                    labels.insert(
-                        "app.kubernetes.io/managed-by".to_string(),
-                        "berg".to_string(),
-                    );
-                    labels.insert(
-                        "app.kubernetes.io/component".to_string(),
-                        "flag-executable".to_string(),
-                    );
-                    labels
-                }),
-                ..Default::default()
-            },
-            binary_data: Some(binary_data),
-            ..Default::default()
-        };
+    if let Some(ref executable) = dynamic_flag.executable {
+        let flag = flags.get(executable.flag_name.as_deref())?;
+        let cm = build_executable_configmap(executable.flag_name.as_deref(), flag, namespace)?;
 
         match api.create(&PostParams::default(), &cm).await {
             Ok(_) => info!("Created flag executable ConfigMap in {}", namespace),
@@ -98,3 +47,105 @@ pub async fn create_flag_configmap(
 
     Ok(())
 }
+
+/// update_flag_configmap overwrites the flag ConfigMaps with the flag currently in the spec
+pub async fn update_flag_configmap(
+    dynamic_flag: &DynamicFlag,
+    flags: &InstanceFlags,
+    namespace: &str,
+    ctx: &Context,
+) -> Result<()> {
+    let api: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), namespace);
+
+    if let Some(ref content) = dynamic_flag.content {
+        let flag_name = content.flag_name.as_deref();
+        let cm = build_content_configmap(flag_name, flags.get(flag_name)?, namespace);
+        api.patch(
+            &flag::object_name("content", flag_name),
+            &PatchParams::default(),
+            &Patch::Merge(&cm),
+        )
+        .await?;
+        info!("Updated flag content ConfigMap in {}", namespace);
+    }
+
+    if let Some(ref executable) = dynamic_flag.executable {
+        let flag_name = executable.flag_name.as_deref();
+        let cm = build_executable_configmap(flag_name, flags.get(flag_name)?, namespace)?;
+        api.patch(
+            &flag::object_name("executable", flag_name),
+            &PatchParams::default(),
+            &Patch::Merge(&cm),
+        )
+        .await?;
+        info!("Updated flag executable ConfigMap in {}", namespace);
+    }
+
+    Ok(())
+}
+
+fn build_content_configmap(flag_name: Option<&str>, flag: &str, namespace: &str) -> ConfigMap {
+    let flag_content = format!("{}\n", flag);
+
+    let mut data = BTreeMap::new();
+    data.insert("content".to_string(), flag_content);
+
+    ConfigMap {
+        metadata: kube::api::ObjectMeta {
+            name: Some(flag::object_name("content", flag_name)),
+            namespace: Some(namespace.to_string()),
+            labels: Some({
+                let mut labels = BTreeMap::new();
+                labels.insert(
+                    "app.kubernetes.io/managed-by".to_string(),
+                    "berg".to_string(),
+                );
+                labels.insert(
+                    "app.kubernetes.io/component".to_string(),
+                    "flag-content".to_string(),
+                );
+                labels
+            }),
+            ..Default::default()
+        },
+        data: Some(data),
+        ..Default::default()
+    }
+}
+
+fn build_executable_configmap(
+    flag_name: Option<&str>,
+    flag: &str,
+    namespace: &str,
+) -> Result<ConfigMap> {
+    // Generate minimal ELF executable that outputs the flag
+    let elf_binary = flag::executable::generate_elf_executable(flag)?;
+
+    let mut binary_data = BTreeMap::new();
+    binary_data.insert(
+        "executable".to_string(),
+        k8s_openapi::ByteString(elf_binary),
+    );
+
+    Ok(ConfigMap {
+        metadata: kube::api::ObjectMeta {
+            name: Some(flag::object_name("executable", flag_name)),
+            namespace: Some(namespace.to_string()),
+            labels: Some({
+                let mut labels = BTreeMap::new();
+                labels.insert(
+                    "app.kubernetes.io/managed-by".to_string(),
+                    "berg".to_string(),
+                );
+                labels.insert(
+                    "app.kubernetes.io/component".to_string(),
+                    "flag-executable".to_string(),
+                );
+                labels
+            }),
+            ..Default::default()
+        },
+        binary_data: Some(binary_data),
+        ..Default::default()
+    })
+}
@@ -1,31 +1,44 @@
 use crate::{
-    crds::{Challenge, ChallengeInstance, ChallengeInstanceClass, ContainerSpec, ServiceEndpoint},
+    crds::{
+        Challenge, ChallengeInstance, ChallengeInstanceClass, ContainerSpec, DynamicFlag,
+        SeccompProfileConfig, SeccompProfileType, ServiceEndpoint,
+    },
     error::{self, Result},
-    flag,
-    reconciler::Context,
-    resources::labels,
+    flag::{self, InstanceFlags},
+    reconciler::{rotation, security, Context},
+    resources::{labels, pod::PodGroup, volume},
+    utils,
 };
 use k8s_openapi::{
     api::{
-        apps::v1::{Deployment, DeploymentSpec},
+        apps::v1::{Deployment, DeploymentSpec, DeploymentStrategy},
         core::v1::{
-            Capabilities, Container, EnvVar, Pod, PodSpec, PodTemplateSpec, ResourceRequirements,
-            SecurityContext,
+            Capabilities, Container, EnvVar, Pod, PodSecurityContext, PodSpec, PodTemplateSpec,
+            ResourceRequirements, SeccompProfile, SecurityContext, Volume,
         },
     },
     apimachinery::pkg::{api::resource::Quantity, apis::meta::v1::LabelSelector},
 };
 use kube::{
-    api::{Api, ListParams, PostParams},
+    api::{Api, ListParams, Patch, PatchParams, PostParams},
     Client, Resource,
 };
 use std::collections::BTreeMap;
 use tracing::{debug, info};
 
+/// Pod template annotation carrying the hash of the deployed flag
+/// Changing it rolls the deployment so the new flag is picked up
+const FLAG_HASH_ANNOTATION: &str = "berg.norelect.ch/flag-hash";
+
+/// Pod template annotation carrying the last applied reset generation
+const RESET_GENERATION_ANNOTATION: &str = "berg.norelect.ch/reset-generation";
+
+#[allow(clippy::too_many_arguments)]
 pub async fn reconcile(
     instance: &ChallengeInstance,
     challenge: &Challenge,
-    container_spec: &ContainerSpec,
+    pod: &PodGroup<'_>,
+    flags: &InstanceFlags,
     namespace: &str,
     class: &ChallengeInstanceClass,
     endpoints: &[ServiceEndpoint],
@@ -36,38 +49,350 @@ pub async fn reconcile(
     let deployment = build_deployment(
         instance,
         challenge,
-        container_spec,
+        pod,
+        flags,
         namespace,
         class,
         endpoints,
-        ctx,
+        &ctx.config.flag_hash_key,
     )?;
 
     match api.create(&PostParams::default(), &deployment).await {
         Ok(_) => {
-            info!(
-                "Created deployment {} in {}",
-                container_spec.hostname, namespace
-            );
+            info!("Created deployment {} in {}", pod.name, namespace);
             Ok(())
         }
         Err(kube::Error::Api(ae)) if ae.code == 409 => {
-            debug!("Deployment {} already exists", container_spec.hostname);
+            debug!("Deployment {} already exists", pod.name);
             Ok(())
         }
         Err(e) => Err(e.into()),
     }
 }
 
+/// rotate_flag patches the pod template with the current flags, rolling the deployment
+/// returns false if the deployment already runs with these flags
+pub async fn rotate_flag(
+    pod: &PodGroup<'_>,
+    flags: &InstanceFlags,
+    namespace: &str,
+    ctx: &Context,
+) -> Result<bool> {
+    let api: Api<Deployment> = Api::namespaced(ctx.client.clone(), namespace);
+
+    let Some(flag_hash) =
+        rotation::pod_flag_hash(&ctx.config.flag_hash_key, &pod.containers, flags)?
+    else {
+        return Ok(false);
+    };
+    let current = api.get(pod.name).await?;
+    let current_hash = current
+        .spec
+        .and_then(|s| s.template.metadata)
+        .and_then(|m| m.annotations)
+        .and_then(|a| a.get(FLAG_HASH_ANNOTATION).cloned());
+    if current_hash.as_deref() == Some(flag_hash.as_str()) {
+        return Ok(false);
+    }
+
+    let patch = serde_json::json!({
+        "spec": {
+            "template": {
+                "metadata": {
+                    "annotations": {
+                        FLAG_HASH_ANNOTATION: flag_hash
+                    }
+                },
+                "spec": flag_env_patch(pod, flags)?
+            }
+        }
+    });
+
+    api.patch(pod.name, &PatchParams::default(), &Patch::Strategic(&patch))
+        .await?;
+
+    info!(
+        "Rolled deployment {} in {} for flag rotation",
+        pod.name, namespace
+    );
+    Ok(true)
+}
+
+/// Build the pod spec patch replacing the flag variables of a rotated pod
+/// containers and env are merged by name, so only the flag variables are replaced
+/// Init containers inherit the container env, so they carry the flag variables too
+fn flag_env_patch(pod: &PodGroup<'_>, flags: &InstanceFlags) -> Result<serde_json::Value> {
+    let mut containers = vec![];
+    let mut init_containers = vec![];
+    for container_spec in &pod.containers {
+        let Some(ref dynamic_flag) = container_spec.dynamic_flag else {
+            continue;
+        };
+        let env = flag_env_vars(dynamic_flag, flags)?;
+        containers.push(serde_json::json!({
+            "name": container_spec.hostname,
+            "env": env
+        }));
+        for init in &container_spec.init_containers {
+            // variables set by the init container itself take precedence over the flag
+            let init_env: Vec<&EnvVar> = env
+                .iter()
+                .filter(|e| !init.environment.contains_key(&e.name))
+                .collect();
+            if init_env.is_empty() {
+                continue;
+            }
+            init_containers.push(serde_json::json!({
+                "name": init.name,
+                "env": init_env
+            }));
+        }
+    }
+
+    let mut spec = serde_json::json!({ "containers": containers });
+    if !init_containers.is_empty() {
+        spec["initContainers"] = serde_json::json!(init_containers);
+    }
+    Ok(spec)
+}
+
+/// restart_all rolls every deployment in the instance namespace
+/// the reset generation is written to the pod template so repeated resets keep rolling
+pub async fn restart_all(namespace: &str, reset_generation: i64, ctx: &Context) -> Result<()> {
+    let api: Api<Deployment> = Api::namespaced(ctx.client.clone(), namespace);
+
+    let patch = serde_json::json!({
+        "spec": {
+            "template": {
+                "metadata": {
+                    "annotations": {
+                        RESET_GENERATION_ANNOTATION: reset_generation.to_string()
+                    }
+                }
+            }
+        }
+    });
+
+    let lp = ListParams::default().labels("app.kubernetes.io/managed-by=berg");
+    for deploy in api.list(&lp).await? {
+        let name = deploy.metadata.name.unwrap_or_default();
+        api.patch(&name, &PatchParams::default(), &Patch::Strategic(&patch))
+            .await?;
+        info!("Restarted deployment {} in {}", name, namespace);
+    }
+
+    Ok(())
+}
+
+/// scale_all sets the replica count of every deployment in the instance namespace
+pub async fn scale_all(namespace: &str, replicas: i32, ctx: &Context) -> Result<()> {
+    let api: Api<Deployment> = Api::namespaced(ctx.client.clone(), namespace);
+
+    let patch = serde_json::json!({
+        "spec": {
+            "replicas": replicas
+        }
+    });
+
+    let lp = ListParams::default().labels("app.kubernetes.io/managed-by=berg");
+    for deploy in api.list(&lp).await? {
+        let name = deploy.metadata.name.unwrap_or_default();
+        api.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await?;
+        debug!(
+            "Scaled deployment {} in {} to {}",
+            name, namespace, replicas
+        );
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 fn build_deployment(
     instance: &ChallengeInstance,
     challenge: &Challenge,
-    container_spec: &ContainerSpec,
+    pod: &PodGroup<'_>,
+    flags: &InstanceFlags,
     namespace: &str,
     class: &ChallengeInstanceClass,
     endpoints: &[ServiceEndpoint],
-    _ctx: &Context,
+    flag_hash_key: &[u8],
 ) -> Result<Deployment> {
+    let mut containers = vec![];
+    let mut init_containers = vec![];
+    let mut volumes = vec![];
+    for container_spec in &pod.containers {
+        let (mut container, container_volumes) = build_container(
+            instance,
+            challenge,
+            container_spec,
+            flags,
+            namespace,
+            class,
+            endpoints,
+        )?;
+        for volume in container_volumes {
+            add_volume(&mut volumes, &mut container, volume);
+        }
+        init_containers.extend(build_init_containers(container_spec, &container));
+        containers.push(container);
+    }
+
+    // Build pod annotations
+    let mut pod_annotations = build_bandwidth_annotations(pod, class)?;
+    pod_annotations.insert(
+        "cluster-autoscaler.kubernetes.io/safe-to-evict".to_string(),
+        "false".to_string(),
+    );
+    if let Some(flag_hash) = rotation::pod_flag_hash(flag_hash_key, &pod.containers, flags)? {
+        pod_annotations.insert(FLAG_HASH_ANNOTATION.to_string(), flag_hash);
+    }
+
+    // Build pod template
+    let pod_template = PodTemplateSpec {
+        metadata: Some(kube::api::ObjectMeta {
+            labels: Some(labels::pod_labels(instance, challenge, pod)),
+            annotations: if pod_annotations.is_empty() {
+                None
+            } else {
+                Some(pod_annotations)
+            },
+            ..Default::default()
+        }),
+        spec: Some(PodSpec {
+            hostname: Some(pod.name.to_string()),
+            share_process_namespace: pod.share_process_namespace().then_some(true),
+            init_containers: if init_containers.is_empty() {
+                None
+            } else {
+                Some(init_containers)
+            },
+            containers,
+            volumes: if volumes.is_empty() {
+                None
+            } else {
+                Some(volumes)
+            },
+            image_pull_secrets: Some(
+                class
+                    .spec
+                    .image_pull
+                    .as_ref()
+                    .map(|ip| ip.secret_names.to_owned())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(
+                        |secret_name| k8s_openapi::api::core::v1::LocalObjectReference {
+                            name: secret_name.clone(),
+                        },
+                    )
+                    .collect(),
+            ),
+            runtime_class_name: pod
+                .containers
+                .iter()
+                .find_map(|c| c.runtime_class_name.clone())
+                .or_else(|| {
+                    class
+                        .spec
+                        .security
+                        .as_ref()
+                        .and_then(|s| s.runtime_class_name.clone())
+                }),
+            security_context: build_pod_security_context(&pod.containers, class),
+            enable_service_links: Some(false),
+            automount_service_account_token: Some(false),
+            termination_grace_period_seconds: Some(0),
+            ..Default::default()
+        }),
+    };
+
+    Ok(Deployment {
+        metadata: kube::api::ObjectMeta {
+            name: Some(pod.name.to_string()),
+            namespace: Some(namespace.to_string()),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            labels: Some(labels::resource_labels(instance, challenge)),
+            ..Default::default()
+        },
+        spec: Some(DeploymentSpec {
+            replicas: Some(1),
+            selector: LabelSelector {
+                match_labels: Some(labels::pod_selector_labels(pod.name)),
+                ..Default::default()
+            },
+            template: pod_template,
+            strategy: build_strategy(pod),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// Pods mounting a ReadWriteOnce claim are replaced with Recreate
+/// a rolling update would wait forever for the new pod to attach a claim the old one still holds
+fn build_strategy(pod: &PodGroup<'_>) -> Option<DeploymentStrategy> {
+    let persistent = pod
+        .containers
+        .iter()
+        .flat_map(|c| &c.volumes)
+        .any(|v| v.persistent.is_some());
+    persistent.then(|| DeploymentStrategy {
+        type_: Some("Recreate".to_string()),
+        ..Default::default()
+    })
+}
+
+/// Build the environment variables delivering env and cookie flags
+fn flag_env_vars(dynamic_flag: &DynamicFlag, flags: &InstanceFlags) -> Result<Vec<EnvVar>> {
+    let mut env = vec![];
+    if let Some(ref env_flag) = dynamic_flag.env {
+        env.push(EnvVar {
+            name: env_flag.name.clone(),
+            value: Some(flags.get(env_flag.flag_name.as_deref())?.to_string()),
+            ..Default::default()
+        });
+    }
+    if let Some(ref cookie) = dynamic_flag.cookie {
+        env.push(flag::cookie::build_env(
+            cookie,
+            flags.get(cookie.flag_name.as_deref())?,
+        ));
+    }
+    Ok(env)
+}
+
+/// Add a volume of a container to the pod
+/// Identical volumes are shared, a conflicting one is renamed after its container
+fn add_volume(volumes: &mut Vec<Volume>, container: &mut Container, mut volume: Volume) {
+    match volumes.iter().find(|v| v.name == volume.name) {
+        Some(existing) if *existing == volume => {}
+        Some(_) => {
+            // volume names are DNS labels, which a long hostname would overflow
+            let renamed = utils::shorten_label(&format!("{}-{}", container.name, volume.name));
+            for mount in container.volume_mounts.iter_mut().flatten() {
+                if mount.name == volume.name {
+                    mount.name = renamed.clone();
+                }
+            }
+            volume.name = renamed;
+            volumes.push(volume);
+        }
+        None => volumes.push(volume),
+    }
+}
+
+/// Build a container of a pod together with the volumes it mounts
+fn build_container(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    container_spec: &ContainerSpec,
+    flags: &InstanceFlags,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+    endpoints: &[ServiceEndpoint],
+) -> Result<(Container, Vec<Volume>)> {
     let container_name = &container_spec.hostname;
 
     // Build environment variables
@@ -89,7 +414,13 @@ fn build_deployment(
         ..Default::default()
     });
 
-    for container in &challenge.spec.containers {
+    // auxiliary containers have no services to point at
+    for container in challenge
+        .spec
+        .containers
+        .iter()
+        .filter(|c| !c.is_auxiliary())
+    {
         for port in &container.ports {
             let env_name = format!(
                 "{}_ENDPOINT",
@@ -98,15 +429,10 @@ fn build_deployment(
                     .unwrap_or(port.port.to_string())
                     .to_uppercase()
             );
-            let service_name = format!(
-                "{}-{}",
-                container.hostname,
-                port.name.to_owned().unwrap_or(port.port.to_string())
-            );
-
             let endpoint_value = match port.r#type {
+                // containers are reached through the service named after their hostname
                 crate::crds::PortType::InternalPort => {
-                    format!("{}:{}", service_name, port.port)
+                    format!("{}:{}", container.hostname, port.port)
                 }
                 crate::crds::PortType::PublicPort
                 | crate::crds::PortType::PublicHttpRoute
@@ -137,15 +463,9 @@ fn build_deployment(
         }
     }
 
-    // Add flag if env mode
+    // Add flag if env or cookie mode
     if let Some(ref dynamic_flag) = container_spec.dynamic_flag {
-        if let Some(ref env_flag) = dynamic_flag.env {
-            env_vars.push(EnvVar {
-                name: env_flag.name.clone(),
-                value: Some(instance.spec.flag.clone()),
-                ..Default::default()
-            });
-        }
+        env_vars.extend(flag_env_vars(dynamic_flag, flags)?);
     }
 
     // Build volumes and mounts for content/executable flags
@@ -154,24 +474,31 @@ fn build_deployment(
 
     if let Some(ref dynamic_flag) = container_spec.dynamic_flag {
         if let Some(ref content) = dynamic_flag.content {
-            let (volume, mount) = flag::content::build_volume_mount(content, &instance.spec.flag)?;
+            let path = resolved_flag_path(instance, container_name, "content", &content.path);
+            let (volume, mount) = flag::content::build_volume_mount(content, &path)?;
             volumes.push(volume);
             volume_mounts.push(mount);
         }
 
         if let Some(ref executable) = dynamic_flag.executable {
-            let (volume, mount) =
-                flag::executable::build_volume_mount(executable, &instance.spec.flag)?;
+            let path = resolved_flag_path(instance, container_name, "executable", &executable.path);
+            let (volume, mount) = flag::executable::build_volume_mount(executable, &path)?;
             volumes.push(volume);
             volume_mounts.push(mount);
         }
     }
 
+    for volume_spec in &container_spec.volumes {
+        let (volume, mount) = volume::build_volume_mount(container_spec, volume_spec);
+        volumes.push(volume);
+        volume_mounts.push(mount);
+    }
+
     // Build resource requirements
     let resources = build_resources(container_spec, class);
 
     // Build security context
-    let security_context = build_security_context(container_spec);
+    let security_context = build_security_context(container_spec, class);
 
     // Build container ports
     let container_ports = container_spec
@@ -189,6 +516,9 @@ fn build_deployment(
     let container = Container {
         name: container_name.clone(),
         image: Some(container_spec.image.clone()),
+        command: (!container_spec.command.is_empty()).then(|| container_spec.command.clone()),
+        args: (!container_spec.args.is_empty()).then(|| container_spec.args.clone()),
+        working_dir: container_spec.working_dir.clone(),
         image_pull_policy: class.spec.image_pull.as_ref().map(|ip| ip.policy.clone()),
         env: if env_vars.is_empty() {
             None
@@ -217,90 +547,60 @@ fn build_deployment(
         security_context: Some(security_context),
         ..Default::default()
     };
+    Ok((container, volumes))
+}
 
-    // Build pod annotations
-    let mut pod_annotations = BTreeMap::new();
-    if let Some(ref egress) = container_spec.egress_bandwidth {
-        pod_annotations.insert("kubernetes.io/egress-bandwidth".to_string(), egress.clone());
-    }
-    if let Some(ref ingress) = container_spec.ingress_bandwidth {
-        pod_annotations.insert(
-            "kubernetes.io/ingress-bandwidth".to_string(),
-            ingress.clone(),
-        );
-    }
-    pod_annotations.insert(
-        "cluster-autoscaler.kubernetes.io/safe-to-evict".to_string(),
-        "false".to_string(),
-    );
-
-    // Build pod template
-    let pod_template = PodTemplateSpec {
-        metadata: Some(kube::api::ObjectMeta {
-            labels: Some(labels::pod_labels(instance, challenge, container_spec)),
-            annotations: if pod_annotations.is_empty() {
-                None
-            } else {
-                Some(pod_annotations)
-            },
-            ..Default::default()
-        }),
-        spec: Some(PodSpec {
-            hostname: Some(container_name.clone()),
-            containers: vec![container],
-            volumes: if volumes.is_empty() {
-                None
-            } else {
-                Some(volumes)
-            },
-            image_pull_secrets: Some(
-                class
-                    .spec
-                    .image_pull
-                    .as_ref()
-                    .map(|ip| ip.secret_names.to_owned())
-                    .unwrap_or_default()
-                    .iter()
-                    .map(
-                        |secret_name| k8s_openapi::api::core::v1::LocalObjectReference {
-                            name: secret_name.clone(),
-                        },
-                    )
-                    .collect(),
-            ),
-            runtime_class_name: container_spec.runtime_class_name.clone().or_else(|| {
-                class
-                    .spec
-                    .security
-                    .as_ref()
-                    .and_then(|s| s.runtime_class_name.clone())
-            }),
-            enable_service_links: Some(false),
-            automount_service_account_token: Some(false),
-            termination_grace_period_seconds: Some(0),
-            ..Default::default()
-        }),
-    };
+/// Build the init containers of a container
+/// They inherit image, environment, flag mounts, resources and security context of the container
+fn build_init_containers(container_spec: &ContainerSpec, container: &Container) -> Vec<Container> {
+    container_spec
+        .init_containers
+        .iter()
+        .map(|init| {
+            let mut env = container.env.clone().unwrap_or_default();
+            for (key, value) in &init.environment {
+                env.retain(|e| e.name != *key);
+                env.push(EnvVar {
+                    name: key.clone(),
+                    value: Some(value.clone()),
+                    ..Default::default()
+                });
+            }
 
-    Ok(Deployment {
-        metadata: kube::api::ObjectMeta {
-            name: Some(container_name.clone()),
-            namespace: Some(namespace.to_string()),
-            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
-            labels: Some(labels::resource_labels(instance, challenge)),
-            ..Default::default()
-        },
-        spec: Some(DeploymentSpec {
-            replicas: Some(1),
-            selector: LabelSelector {
-                match_labels: Some(labels::pod_selector_labels(container_spec)),
+            Container {
+                name: init.name.clone(),
+                image: init.image.clone().or_else(|| container.image.clone()),
+                image_pull_policy: container.image_pull_policy.clone(),
+                command: (!init.command.is_empty()).then(|| init.command.clone()),
+                args: (!init.args.is_empty()).then(|| init.args.clone()),
+                working_dir: init.working_dir.clone(),
+                env: if env.is_empty() { None } else { Some(env) },
+                volume_mounts: container.volume_mounts.clone(),
+                resources: container.resources.clone(),
+                security_context: container.security_context.clone(),
                 ..Default::default()
-            },
-            template: pod_template,
-            ..Default::default()
-        }),
-        ..Default::default()
-    })
+            }
+        })
+        .collect()
+}
+
+/// Look up the flag path pinned in the instance status
+/// Instances created before paths were recorded fall back to a fresh substitution
+fn resolved_flag_path(
+    instance: &ChallengeInstance,
+    hostname: &str,
+    kind: &str,
+    path: &str,
+) -> String {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| {
+            s.resolved_flag_paths
+                .get(&flag::entropy::path_key(hostname, kind))
+        })
+        .cloned()
+        .unwrap_or_else(|| flag::entropy::substitute_entropy(path))
 }
 
 fn build_resources(
@@ -374,6 +674,30 @@ fn build_resources(
         requests.insert("memory".to_string(), Quantity(memory_request));
     }
 
+    // Ephemeral storage
+    // the apiserver rejects pods without a request once the quota tracks it
+    let quota_tracks_storage = class
+        .spec
+        .quota
+        .as_ref()
+        .is_some_and(|q| q.ephemeral_storage.is_some());
+    let limit_range_defaults_storage = class.spec.limit_range.as_ref().is_some_and(|l| {
+        l.default.contains_key("ephemeral-storage")
+            || l.default_request.contains_key("ephemeral-storage")
+    });
+    if quota_tracks_storage && !limit_range_defaults_storage {
+        let ephemeral_storage_request = class
+            .spec
+            .default_resources
+            .as_ref()
+            .and_then(|r| r.ephemeral_storage_request.clone())
+            .unwrap_or_else(|| "64Mi".to_string());
+        requests.insert(
+            "ephemeral-storage".to_string(),
+            Quantity(ephemeral_storage_request),
+        );
+    }
+
     ResourceRequirements {
         limits: Some(limits),
         requests: Some(requests),
@@ -381,20 +705,56 @@ fn build_resources(
     }
 }
 
-fn build_security_context(container_spec: &ContainerSpec) -> SecurityContext {
-    let capabilities_to_add = container_spec.additional_capabilities.clone();
-    let mut capabilities_to_drop = vec![];
+fn build_security_context(
+    container_spec: &ContainerSpec,
+    class: &ChallengeInstanceClass,
+) -> SecurityContext {
+    let security = class.spec.security.as_ref();
+
+    // Forbidden capabilities are rejected before anything is deployed
+    let mut capabilities_to_add: Vec<String> = security
+        .map(|s| s.default_capabilities.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .chain(&container_spec.additional_capabilities)
+        .map(|c| security::normalize_capability(c))
+        .collect();
+    let mut capabilities_to_drop: Vec<String> = security
+        .map(|s| s.drop_capabilities.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|c| security::normalize_capability(c))
+        .collect();
 
     // Drop DAC_OVERRIDE if executable flag mode
     if let Some(ref dynamic_flag) = container_spec.dynamic_flag {
         if dynamic_flag.executable.is_some() {
+            capabilities_to_add.retain(|c| c != "DAC_OVERRIDE");
             capabilities_to_drop.push("DAC_OVERRIDE".to_string());
         }
     }
+    capabilities_to_add.sort();
+    capabilities_to_add.dedup();
+    capabilities_to_drop.sort();
+    capabilities_to_drop.dedup();
+
+    // user and seccomp overrides only apply to this container, not the rest of its pod
+    let overrides = container_spec.security_context.as_ref();
 
     SecurityContext {
+        run_as_non_root: overrides.and_then(|o| o.run_as_non_root),
+        run_as_user: overrides.and_then(|o| o.run_as_user),
+        run_as_group: overrides.and_then(|o| o.run_as_group),
+        seccomp_profile: overrides
+            .and_then(|o| o.seccomp_profile.clone())
+            .map(seccomp_profile),
         privileged: Some(false),
-        allow_privilege_escalation: Some(true),
+        allow_privilege_escalation: Some(
+            security
+                .and_then(|s| s.allow_privilege_escalation)
+                .unwrap_or(true),
+        ),
+        read_only_root_filesystem: security.and_then(|s| s.read_only_root_filesystem),
         capabilities: Some(Capabilities {
             add: if capabilities_to_add.is_empty() {
                 None
@@ -411,11 +771,64 @@ fn build_security_context(container_spec: &ContainerSpec) -> SecurityContext {
     }
 }
 
+/// Build the pod security context from the class
+/// Only the pod-scoped fsGroup and supplementalGroups may be overridden by the challenge,
+/// the containers of a pod agree on them
+fn build_pod_security_context(
+    containers: &[&ContainerSpec],
+    class: &ChallengeInstanceClass,
+) -> Option<PodSecurityContext> {
+    let class_config = class
+        .spec
+        .security
+        .as_ref()
+        .and_then(|s| s.pod_security_context.clone());
+    let overrides = containers
+        .iter()
+        .filter_map(|c| c.security_context.as_ref());
+    let fs_group = overrides.clone().find_map(|c| c.fs_group);
+    let supplemental_groups = overrides
+        .clone()
+        .find_map(|c| c.supplemental_groups.clone());
+    if class_config.is_none() && fs_group.is_none() && supplemental_groups.is_none() {
+        return None;
+    }
+    let config = class_config.unwrap_or_default();
+
+    Some(PodSecurityContext {
+        run_as_non_root: config.run_as_non_root,
+        run_as_user: config.run_as_user,
+        run_as_group: config.run_as_group,
+        fs_group: fs_group.or(config.fs_group),
+        supplemental_groups: supplemental_groups.or(config.supplemental_groups),
+        seccomp_profile: config.seccomp_profile.map(seccomp_profile),
+        ..Default::default()
+    })
+}
+
+fn seccomp_profile(profile: SeccompProfileConfig) -> SeccompProfile {
+    SeccompProfile {
+        type_: match profile.r#type {
+            SeccompProfileType::RuntimeDefault => "RuntimeDefault",
+            SeccompProfileType::Unconfined => "Unconfined",
+            SeccompProfileType::Localhost => "Localhost",
+        }
+        .to_string(),
+        localhost_profile: profile.localhost_profile,
+    }
+}
+
 pub async fn check_pods_ready(client: &Client, namespace: &str) -> Result<bool> {
     let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
+    let deployments: Api<Deployment> = Api::namespaced(client.clone(), namespace);
 
     let lp = ListParams::default().labels("app.kubernetes.io/managed-by=berg");
 
+    // pods of a previous template, e.g. serving a rotated flag, are still ready during a rollout
+    if !deployments.list(&lp).await?.iter().all(rolled_out) {
+        return Ok(false);
+    }
+
     let pod_list = pods.list(&lp).await?;
 
     if pod_list.items.is_empty() {
@@ -451,8 +864,372 @@ pub async fn check_pods_ready(client: &Client, namespace: &str) -> Result<bool>
     Ok(true)
 }
 
+/// Check if a deployment runs only pods of its current template
+fn rolled_out(deployment: &Deployment) -> bool {
+    let Some(ref status) = deployment.status else {
+        return false;
+    };
+    let replicas = deployment
+        .spec
+        .as_ref()
+        .and_then(|s| s.replicas)
+        .unwrap_or(1);
+
+    status.observed_generation >= deployment.metadata.generation
+        && status.updated_replicas.unwrap_or_default() == replicas
+        && status.replicas.unwrap_or_default() == replicas
+}
+
+/// Collect why deployments in a namespace can't create their pods because of the ResourceQuota
+pub async fn quota_failures(client: &Client, namespace: &str) -> Result<Vec<String>> {
+    let deployments: Api<Deployment> = Api::namespaced(client.clone(), namespace);
+    let lp = ListParams::default().labels("app.kubernetes.io/managed-by=berg");
+
+    Ok(deployments
+        .list(&lp)
+        .await?
+        .into_iter()
+        .flat_map(|deploy| deploy.status.and_then(|s| s.conditions).unwrap_or_default())
+        .filter(|c| c.type_ == "ReplicaFailure" && c.status == "True")
+        .filter_map(|c| c.message)
+        .filter(|message| message.contains("exceeded quota") || message.contains("failed quota"))
+        .collect())
+}
+
 pub async fn check_pods_healthy(client: &Client, namespace: &str) -> Result<bool> {
     // For now, same as check_pods_ready
     // In production, this would check liveness probes and other health indicators
     check_pods_ready(client, namespace).await
 }
+
+/// Bandwidth annotations of a pod
+/// The first member setting a limit overrides the default of the class
+fn build_bandwidth_annotations(
+    pod: &PodGroup<'_>,
+    class: &ChallengeInstanceClass,
+) -> Result<BTreeMap<String, String>> {
+    let network = class.spec.network.as_ref();
+    let limits = [
+        (
+            "kubernetes.io/egress-bandwidth",
+            pod.containers
+                .iter()
+                .find_map(|c| c.egress_bandwidth.as_ref())
+                .or(network.and_then(|n| n.egress_bandwidth.as_ref())),
+        ),
+        (
+            "kubernetes.io/ingress-bandwidth",
+            pod.containers
+                .iter()
+                .find_map(|c| c.ingress_bandwidth.as_ref())
+                .or(network.and_then(|n| n.ingress_bandwidth.as_ref())),
+        ),
+    ];
+
+    let mut annotations = BTreeMap::new();
+    for (annotation, limit) in limits {
+        let Some(limit) = limit else {
+            continue;
+        };
+        // the CNI ignores malformed limits, which would leave the pod unlimited
+        if !utils::is_quantity(limit) {
+            return Err(error::Error::ConfigError(format!(
+                "Invalid bandwidth {} for pod {}",
+                limit, pod.name
+            )));
+        }
+        annotations.insert(annotation.to_string(), limit.clone());
+    }
+
+    Ok(annotations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::{EmptyDirVolumeSource, VolumeMount};
+    use serde_json::json;
+
+    fn class(security: serde_json::Value) -> ChallengeInstanceClass {
+        crate::test_fixtures::class(json!({ "security": security }))
+    }
+
+    fn container(security_context: serde_json::Value) -> ContainerSpec {
+        serde_json::from_value(json!({
+            "hostname": "web",
+            "image": "nginx",
+            "securityContext": security_context
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pod_security_context_from_class() {
+        let class = class(json!({
+            "podSecurityContext": {
+                "runAsNonRoot": true,
+                "runAsUser": 1000,
+                "fsGroup": 2000,
+                "seccompProfile": { "type": "RuntimeDefault" }
+            }
+        }));
+
+        let context = build_pod_security_context(&[&container(json!(null))], &class).unwrap();
+        assert_eq!(context.run_as_non_root, Some(true));
+        assert_eq!(context.run_as_user, Some(1000));
+        assert_eq!(context.fs_group, Some(2000));
+        assert_eq!(context.seccomp_profile.unwrap().type_, "RuntimeDefault");
+    }
+
+    #[test]
+    fn test_pod_security_context_container_override() {
+        let hardened = class(json!({
+            "podSecurityContext": { "runAsNonRoot": true, "runAsUser": 1000, "fsGroup": 2000 }
+        }));
+
+        // e.g. a challenge that has to start as root and drop privileges itself
+        let root = container(json!({ "runAsNonRoot": false, "runAsUser": 0, "fsGroup": 3000 }));
+        let context = build_pod_security_context(&[&root], &hardened).unwrap();
+        assert_eq!(context.run_as_non_root, Some(true));
+        assert_eq!(context.run_as_user, Some(1000));
+        assert_eq!(context.fs_group, Some(3000));
+
+        let container_context = build_security_context(&root, &hardened);
+        assert_eq!(container_context.run_as_non_root, Some(false));
+        assert_eq!(container_context.run_as_user, Some(0));
+    }
+
+    #[test]
+    fn test_security_context_grouped_containers() {
+        let hardened = class(json!({
+            "podSecurityContext": { "runAsNonRoot": true, "runAsUser": 1000 }
+        }));
+        let root = container(json!({ "runAsNonRoot": false, "runAsUser": 0 }));
+        let mut bot = container(json!(null));
+        bot.hostname = "bot".to_string();
+
+        // the root override stays on its own container
+        let context = build_pod_security_context(&[&root, &bot], &hardened).unwrap();
+        assert_eq!(context.run_as_user, Some(1000));
+        assert_eq!(context.run_as_non_root, Some(true));
+        assert_eq!(
+            build_security_context(&root, &hardened).run_as_user,
+            Some(0)
+        );
+        let bot_context = build_security_context(&bot, &hardened);
+        assert_eq!(bot_context.run_as_user, None);
+        assert_eq!(bot_context.run_as_non_root, None);
+    }
+
+    #[test]
+    fn test_pod_security_context_unset() {
+        assert!(
+            build_pod_security_context(&[&container(json!(null))], &class(json!(null))).is_none()
+        );
+    }
+
+    #[test]
+    fn test_init_containers_inherit_container() {
+        let spec: ContainerSpec = serde_json::from_value(json!({
+            "hostname": "db",
+            "image": "postgres:16",
+            "initContainers": [
+                { "name": "seed", "command": ["/seed.sh"], "environment": { "MODE": "seed" } },
+                { "name": "migrate", "image": "migrate:latest", "args": ["up"] }
+            ]
+        }))
+        .unwrap();
+        let container = Container {
+            name: "db".to_string(),
+            image: Some("postgres:16".to_string()),
+            env: Some(vec![
+                EnvVar {
+                    name: "MODE".to_string(),
+                    value: Some("serve".to_string()),
+                    ..Default::default()
+                },
+                EnvVar {
+                    name: "FLAG".to_string(),
+                    value: Some("flag{test}".to_string()),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let init = build_init_containers(&spec, &container);
+        assert_eq!(init.len(), 2);
+
+        assert_eq!(init[0].image.as_deref(), Some("postgres:16"));
+        assert_eq!(init[0].command, Some(vec!["/seed.sh".to_string()]));
+        let env = init[0].env.as_ref().unwrap();
+        assert_eq!(env.len(), 2);
+        assert!(env
+            .iter()
+            .any(|e| e.name == "MODE" && e.value.as_deref() == Some("seed")));
+        assert!(env.iter().any(|e| e.name == "FLAG"));
+
+        assert_eq!(init[1].image.as_deref(), Some("migrate:latest"));
+        assert_eq!(init[1].command, None);
+        assert_eq!(init[1].args, Some(vec!["up".to_string()]));
+    }
+
+    #[test]
+    fn test_rolled_out() {
+        let deployment = |generation: i64, updated: i32, replicas: i32| -> Deployment {
+            serde_json::from_value(json!({
+                "metadata": { "name": "web", "generation": 2 },
+                "spec": {
+                    "replicas": 1,
+                    "selector": {},
+                    "template": {}
+                },
+                "status": {
+                    "observedGeneration": generation,
+                    "updatedReplicas": updated,
+                    "replicas": replicas
+                }
+            }))
+            .unwrap()
+        };
+
+        assert!(rolled_out(&deployment(2, 1, 1)));
+        // the patched template has not been seen yet
+        assert!(!rolled_out(&deployment(1, 1, 1)));
+        // the old pod is still around
+        assert!(!rolled_out(&deployment(2, 1, 2)));
+    }
+
+    #[test]
+    fn test_bandwidth_annotations() {
+        let mut class = class(json!(null));
+        class.spec.network = serde_json::from_value(json!({
+            "egressBandwidth": "10M",
+            "ingressBandwidth": "10M"
+        }))
+        .unwrap();
+        let mut container = container(json!(null));
+        container.ingress_bandwidth = Some("100M".to_string());
+        let pod = PodGroup {
+            name: "web",
+            containers: vec![&container],
+        };
+
+        let annotations = build_bandwidth_annotations(&pod, &class).unwrap();
+        assert_eq!(annotations["kubernetes.io/egress-bandwidth"], "10M");
+        assert_eq!(annotations["kubernetes.io/ingress-bandwidth"], "100M");
+
+        container.ingress_bandwidth = Some("100 Mbit".to_string());
+        let pod = PodGroup {
+            name: "web",
+            containers: vec![&container],
+        };
+        assert!(build_bandwidth_annotations(&pod, &class).is_err());
+    }
+
+    #[test]
+    fn test_ephemeral_storage_request() {
+        let mut class = class(json!(null));
+        let container = container(json!(null));
+        let requests =
+            |class: &ChallengeInstanceClass| build_resources(&container, class).requests.unwrap();
+        assert!(!requests(&class).contains_key("ephemeral-storage"));
+
+        class.spec.quota = serde_json::from_value(json!({ "ephemeralStorage": "4Gi" })).unwrap();
+        assert_eq!(
+            requests(&class)["ephemeral-storage"],
+            Quantity("64Mi".to_string())
+        );
+
+        // the LimitRange default already satisfies the quota
+        class.spec.limit_range =
+            serde_json::from_value(json!({ "defaultRequest": { "ephemeral-storage": "128Mi" } }))
+                .unwrap();
+        assert!(!requests(&class).contains_key("ephemeral-storage"));
+    }
+
+    #[test]
+    fn test_flag_env_patch() {
+        let spec: ContainerSpec = serde_json::from_value(json!({
+            "hostname": "web",
+            "image": "nginx",
+            "dynamicFlag": { "env": { "name": "FLAG" } },
+            "initContainers": [
+                { "name": "seed", "command": ["/seed.sh"] },
+                { "name": "override", "environment": { "FLAG": "fake" } }
+            ]
+        }))
+        .unwrap();
+        let pod = PodGroup {
+            name: "web",
+            containers: vec![&spec],
+        };
+        let flags = InstanceFlags {
+            default: "flag{rotated}".to_string(),
+            named: BTreeMap::new(),
+        };
+
+        let patch = flag_env_patch(&pod, &flags).unwrap();
+        let flag_env = json!([{ "name": "FLAG", "value": "flag{rotated}" }]);
+        assert_eq!(
+            patch["containers"],
+            json!([{ "name": "web", "env": flag_env }])
+        );
+        assert_eq!(
+            patch["initContainers"],
+            json!([{ "name": "seed", "env": flag_env }])
+        );
+    }
+
+    #[test]
+    fn test_strategy() {
+        let mut container = container(json!(null));
+        let pod = PodGroup {
+            name: "web",
+            containers: vec![&container],
+        };
+        assert!(build_strategy(&pod).is_none());
+
+        container.volumes = serde_json::from_value(json!([{
+            "name": "data",
+            "mountPath": "/data",
+            "persistent": { "size": "1Gi" }
+        }]))
+        .unwrap();
+        let pod = PodGroup {
+            name: "web",
+            containers: vec![&container],
+        };
+        assert_eq!(
+            build_strategy(&pod).unwrap().type_.as_deref(),
+            Some("Recreate")
+        );
+    }
+
+    #[test]
+    fn test_add_volume_long_hostname() {
+        let volume = |medium: &str| Volume {
+            name: "volume-data".to_string(),
+            empty_dir: Some(EmptyDirVolumeSource {
+                medium: Some(medium.to_string()),
+                size_limit: None,
+            }),
+            ..Default::default()
+        };
+        let mut container = Container {
+            name: "a".repeat(63),
+            volume_mounts: Some(vec![VolumeMount {
+                name: "volume-data".to_string(),
+                mount_path: "/data".to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let mut volumes = vec![volume("")];
+
+        add_volume(&mut volumes, &mut container, volume("Memory"));
+        assert_eq!(volumes.len(), 2);
+        assert!(utils::is_dns_label(&volumes[1].name));
+        assert_eq!(container.volume_mounts.unwrap()[0].name, volumes[1].name);
+    }
+}
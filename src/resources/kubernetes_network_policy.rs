@@ -0,0 +1,452 @@
+use crate::{
+    crds::{Challenge, ChallengeInstance, ChallengeInstanceClass, PortType},
+    error::Result,
+    reconciler::{Context, FIELD_MANAGER},
+    resources::{
+        network_policy::{
+            auxiliary_selector, challenge_selector, container_selector, delete_stale, peer_pod,
+            restricted_pods,
+        },
+        pod::{self, PodGroup},
+    },
+};
+use k8s_openapi::{
+    api::networking::v1::{
+        IPBlock, NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyIngressRule,
+        NetworkPolicyPeer, NetworkPolicyPort, NetworkPolicySpec,
+    },
+    apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString},
+};
+use kube::{
+    api::{Api, Patch, PatchParams},
+    Resource,
+};
+use std::collections::BTreeMap;
+use tracing::debug;
+
+/// Label every namespace carries with its own name
+const NAMESPACE_NAME_LABEL: &str = "kubernetes.io/metadata.name";
+
+/// reconcile applies the NetworkPolicies of the challenge instance
+/// server-side apply keeps existing policies in line with the current rules
+/// Returns the rules NetworkPolicies cannot express
+pub async fn reconcile(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+    ctx: &Context,
+) -> Result<Vec<String>> {
+    let api: Api<NetworkPolicy> = Api::namespaced(ctx.client.clone(), namespace);
+
+    let (policies, limitations) = build_policies(instance, challenge, namespace, class);
+    for policy in &policies {
+        let name = policy.metadata.name.clone().unwrap_or_default();
+        api.patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(policy),
+        )
+        .await?;
+        debug!("Applied NetworkPolicy {} in {}", name, namespace);
+    }
+    delete_stale(&api, &policies, namespace).await?;
+
+    Ok(limitations)
+}
+
+/// Build the policies of an instance, mirroring the Cilium policies where possible
+/// NetworkPolicies have no DNS or FQDN rules and cannot tell nodes from other addresses,
+/// so those rules are either widened or dropped and reported as limitations
+fn build_policies(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+) -> (Vec<NetworkPolicy>, Vec<String>) {
+    let groups = pod::groups(challenge);
+    let restricted = restricted_pods(&groups);
+    let has_auxiliary = groups.iter().any(|p| p.is_auxiliary());
+    let (node_cidrs, pod_cidrs) = class
+        .spec
+        .network
+        .as_ref()
+        .map(|n| (n.node_cidrs.as_slice(), n.pod_cidrs.as_slice()))
+        .unwrap_or_default();
+
+    let mut limitations = Vec::new();
+    if !challenge.spec.allow_outbound_traffic || has_auxiliary {
+        limitations.push("DNS lookups cannot be restricted to the instance services".to_string());
+    }
+    if !challenge.spec.allow_outbound_traffic && !challenge.spec.allowed_egress.is_empty() {
+        limitations
+            .push("allowedEgress hosts cannot be allowed by name and stay blocked".to_string());
+    }
+    if node_cidrs.is_empty() {
+        limitations.push("Gateway traffic cannot be restricted to the nodes".to_string());
+    }
+
+    let egress_rules = |peer_rules: Vec<NetworkPolicyEgressRule>| {
+        let mut rules = vec![dns_rule()];
+        rules.extend(peer_rules);
+        rules.push(gateway_rule(class, node_cidrs));
+        if challenge.spec.allow_outbound_traffic {
+            rules.push(NetworkPolicyEgressRule {
+                to: Some(world_blocks(pod_cidrs)),
+                ports: None,
+            });
+        }
+        rules
+    };
+
+    // Only the instance itself, the gateway and NodePort traffic may reach challenge pods
+    let mut ingress_rules = vec![
+        same_namespace_ingress_rule(),
+        NetworkPolicyIngressRule {
+            from: Some(vec![NetworkPolicyPeer {
+                namespace_selector: Some(namespace_selector(&class.spec.gateway.namespace)),
+                ..Default::default()
+            }]),
+            ports: None,
+        },
+    ];
+    let public_ports = challenge
+        .spec
+        .containers
+        .iter()
+        .flat_map(|c| &c.ports)
+        .filter(|p| p.r#type == PortType::PublicPort)
+        .map(|p| policy_port(p.port, &p.protocol))
+        .collect::<Vec<_>>();
+    if pod_cidrs.is_empty() && (!public_ports.is_empty() || challenge.spec.allow_outbound_traffic) {
+        limitations
+            .push("NodePort and outbound traffic cannot exclude other instances".to_string());
+    }
+    if !public_ports.is_empty() {
+        ingress_rules.push(NetworkPolicyIngressRule {
+            from: Some(world_blocks(pod_cidrs)),
+            ports: Some(public_ports),
+        });
+    }
+
+    let mut policies = vec![build_policy(
+        instance,
+        "challenge-network-policy",
+        namespace,
+        challenge_selector(&restricted),
+        ingress_rules.clone(),
+        egress_rules(vec![namespace_rule()]),
+    )];
+
+    for pod in restricted {
+        policies.push(build_policy(
+            instance,
+            &format!("container-{}-network-policy", pod.name),
+            namespace,
+            container_selector(pod.name),
+            ingress_rules.clone(),
+            egress_rules(peer_rules(challenge, pod)),
+        ));
+    }
+
+    if has_auxiliary {
+        policies.push(build_policy(
+            instance,
+            "auxiliary-network-policy",
+            namespace,
+            auxiliary_selector(),
+            vec![same_namespace_ingress_rule()],
+            vec![
+                dns_rule(),
+                namespace_rule(),
+                gateway_rule(class, node_cidrs),
+            ],
+        ));
+    }
+
+    (policies, limitations)
+}
+
+fn build_policy(
+    instance: &ChallengeInstance,
+    name: &str,
+    namespace: &str,
+    pod_selector: LabelSelector,
+    ingress_rules: Vec<NetworkPolicyIngressRule>,
+    egress_rules: Vec<NetworkPolicyEgressRule>,
+) -> NetworkPolicy {
+    NetworkPolicy {
+        metadata: kube::api::ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(namespace.to_string()),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            labels: Some(BTreeMap::from([
+                (
+                    "app.kubernetes.io/managed-by".to_string(),
+                    "berg".to_string(),
+                ),
+                (
+                    "app.kubernetes.io/component".to_string(),
+                    "network-policy".to_string(),
+                ),
+            ])),
+            ..Default::default()
+        },
+        spec: Some(NetworkPolicySpec {
+            pod_selector: Some(pod_selector),
+            policy_types: Some(vec!["Ingress".to_string(), "Egress".to_string()]),
+            ingress: Some(ingress_rules),
+            egress: Some(egress_rules),
+        }),
+    }
+}
+
+/// Traffic to the declared peers of the containers of a pod
+fn peer_rules(challenge: &Challenge, pod: &PodGroup<'_>) -> Vec<NetworkPolicyEgressRule> {
+    pod.containers
+        .iter()
+        .flat_map(|c| c.allowed_peers.iter().flatten())
+        .map(|peer| {
+            // NetworkPolicy ports need a protocol, so take it from the peer's port
+            let peer_ports = challenge
+                .spec
+                .containers
+                .iter()
+                .find(|c| c.hostname == peer.hostname)
+                .map(|c| c.ports.as_slice())
+                .unwrap_or_default();
+            let ports = peer
+                .ports
+                .iter()
+                .map(|port| {
+                    let protocol = peer_ports
+                        .iter()
+                        .find(|p| p.port == *port)
+                        .map_or("TCP", |p| p.protocol.as_str());
+                    policy_port(*port, protocol)
+                })
+                .collect::<Vec<_>>();
+
+            NetworkPolicyEgressRule {
+                to: Some(vec![NetworkPolicyPeer {
+                    pod_selector: Some(container_selector(peer_pod(challenge, &peer.hostname))),
+                    ..Default::default()
+                }]),
+                ports: (!ports.is_empty()).then_some(ports),
+            }
+        })
+        .collect()
+}
+
+/// Port of a rule, the protocol is upper-cased like the ports of the services
+fn policy_port(port: u16, protocol: &str) -> NetworkPolicyPort {
+    NetworkPolicyPort {
+        port: Some(IntOrString::Int(port.into())),
+        protocol: Some(protocol.to_uppercase()),
+        end_port: None,
+    }
+}
+
+fn ip_block(cidr: &str, except: Vec<String>) -> NetworkPolicyPeer {
+    NetworkPolicyPeer {
+        ip_block: Some(IPBlock {
+            cidr: cidr.to_string(),
+            except: (!except.is_empty()).then_some(except),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Every address outside the pod ranges, so the pods of other instances stay excluded
+/// Exceptions have to lie within their block, so they are split by address family
+fn world_blocks(pod_cidrs: &[String]) -> Vec<NetworkPolicyPeer> {
+    let (v6, v4): (Vec<String>, Vec<String>) = pod_cidrs
+        .iter()
+        .cloned()
+        .partition(|cidr| cidr.contains(':'));
+    vec![ip_block("0.0.0.0/0", v4), ip_block("::/0", v6)]
+}
+
+fn namespace_selector(namespace: &str) -> LabelSelector {
+    LabelSelector {
+        match_labels: Some(BTreeMap::from([(
+            NAMESPACE_NAME_LABEL.to_string(),
+            namespace.to_string(),
+        )])),
+        ..Default::default()
+    }
+}
+
+/// Traffic from the other pods in the instance namespace
+fn same_namespace_ingress_rule() -> NetworkPolicyIngressRule {
+    NetworkPolicyIngressRule {
+        from: Some(vec![NetworkPolicyPeer {
+            pod_selector: Some(LabelSelector::default()), // Empty selector matches all
+            ..Default::default()
+        }]),
+        ports: None,
+    }
+}
+
+/// DNS to kube-dns
+fn dns_rule() -> NetworkPolicyEgressRule {
+    NetworkPolicyEgressRule {
+        to: Some(vec![NetworkPolicyPeer {
+            namespace_selector: Some(namespace_selector("kube-system")),
+            pod_selector: Some(LabelSelector {
+                match_labels: Some(BTreeMap::from([(
+                    "k8s-app".to_string(),
+                    "kube-dns".to_string(),
+                )])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }]),
+        ports: Some(vec![policy_port(53, "UDP"), policy_port(53, "TCP")]),
+    }
+}
+
+/// Traffic to the other pods in the instance namespace
+fn namespace_rule() -> NetworkPolicyEgressRule {
+    NetworkPolicyEgressRule {
+        to: Some(vec![NetworkPolicyPeer {
+            pod_selector: Some(LabelSelector::default()), // Empty selector matches all
+            ..Default::default()
+        }]),
+        ports: None,
+    }
+}
+
+/// Traffic to the gateway ports of the nodes
+/// Nodes cannot be selected, so without their ranges any address is allowed
+fn gateway_rule(class: &ChallengeInstanceClass, node_cidrs: &[String]) -> NetworkPolicyEgressRule {
+    NetworkPolicyEgressRule {
+        to: (!node_cidrs.is_empty()).then(|| {
+            node_cidrs
+                .iter()
+                .map(|cidr| ip_block(cidr, vec![]))
+                .collect()
+        }),
+        ports: Some(vec![
+            policy_port(class.spec.gateway.http_port, "TCP"),
+            policy_port(class.spec.gateway.tls_port, "TCP"),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{resources::labels::CONTAINER_LABEL, test_fixtures::instance};
+    use serde_json::json;
+
+    fn challenge(containers: serde_json::Value) -> Challenge {
+        crate::test_fixtures::challenge(json!({
+            "allowOutboundTraffic": false,
+            "allowedEgress": [{ "fqdn": "api.github.com" }],
+            "containers": containers
+        }))
+    }
+
+    fn class() -> ChallengeInstanceClass {
+        crate::test_fixtures::class(json!({ "network": { "policyBackend": "kubernetes" } }))
+    }
+
+    #[test]
+    fn test_policies() {
+        let challenge = challenge(json!([
+            {
+                "hostname": "web",
+                "image": "web",
+                "ports": [{ "port": 1337, "protocol": "tcp", "type": "publicPort" }],
+                "allowedPeers": [{ "hostname": "dns", "ports": [53] }]
+            },
+            {
+                "hostname": "dns",
+                "image": "dns",
+                "ports": [{ "port": 53, "protocol": "udp" }]
+            },
+            { "hostname": "bot", "image": "bot", "role": "auxiliary" }
+        ]));
+
+        let (policies, limitations) =
+            build_policies(&instance(), &challenge, "instance-ns", &class());
+        assert_eq!(policies.len(), 3);
+        assert_eq!(limitations.len(), 4);
+
+        let challenge_spec = policies[0].spec.as_ref().unwrap();
+        let ingress = challenge_spec.ingress.as_ref().unwrap();
+        assert_eq!(
+            ingress[1].from.as_ref().unwrap()[0].namespace_selector,
+            Some(namespace_selector("berg"))
+        );
+        assert_eq!(
+            ingress[2].ports.as_ref().unwrap()[0].protocol.as_deref(),
+            Some("TCP")
+        );
+        // outbound traffic is forbidden, so nothing opens the world
+        assert!(!challenge_spec
+            .egress
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|rule| rule.to.iter().flatten().any(|peer| peer.ip_block.is_some())));
+
+        // the peer protocol is taken from the peer's port
+        let web = policies[1].spec.as_ref().unwrap().egress.as_ref().unwrap();
+        let dns = web
+            .iter()
+            .find(|rule| {
+                rule.to.as_ref().is_some_and(|to| {
+                    to[0]
+                        .pod_selector
+                        .as_ref()
+                        .and_then(|s| s.match_labels.as_ref())
+                        .is_some_and(|labels| labels.get(CONTAINER_LABEL).is_some())
+                })
+            })
+            .unwrap();
+        assert_eq!(
+            dns.ports.as_ref().unwrap()[0].protocol.as_deref(),
+            Some("UDP")
+        );
+
+        let auxiliary = policies[2].spec.as_ref().unwrap();
+        assert_eq!(auxiliary.pod_selector, Some(auxiliary_selector()));
+        assert_eq!(auxiliary.ingress.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cidrs() {
+        let challenge = challenge(json!([{
+            "hostname": "web",
+            "image": "web",
+            "ports": [{ "port": 1337, "protocol": "TCP", "type": "publicPort" }]
+        }]));
+        let mut class = class();
+        class.spec.network = serde_json::from_value(json!({
+            "policyBackend": "kubernetes",
+            "nodeCidrs": ["10.0.0.0/24"],
+            "podCidrs": ["10.244.0.0/16", "fd00:10:244::/56"]
+        }))
+        .unwrap();
+
+        let (policies, limitations) =
+            build_policies(&instance(), &challenge, "instance-ns", &class);
+        // only the DNS and allowedEgress rules are left over
+        assert_eq!(limitations.len(), 2);
+
+        let spec = policies[0].spec.as_ref().unwrap();
+        let gateway = &spec.egress.as_ref().unwrap()[2];
+        assert_eq!(gateway.to, Some(vec![ip_block("10.0.0.0/24", vec![])]));
+
+        let node_port = &spec.ingress.as_ref().unwrap()[2];
+        assert_eq!(
+            node_port.from,
+            Some(vec![
+                ip_block("0.0.0.0/0", vec!["10.244.0.0/16".to_string()]),
+                ip_block("::/0", vec!["fd00:10:244::/56".to_string()]),
+            ])
+        );
+    }
+}
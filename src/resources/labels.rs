@@ -1,8 +1,9 @@
 use kube::ResourceExt;
 
 use crate::{
-    crds::{Challenge, ChallengeInstance, ContainerSpec},
+    crds::{Challenge, ChallengeInstance, ChallengeInstanceClass},
     reconciler::Context,
+    resources::pod::PodGroup,
 };
 use std::collections::BTreeMap;
 
@@ -40,8 +41,34 @@ pub fn common_labels(
 }
 
 /// Generate labels for namespace
-pub fn namespace_labels(instance: &ChallengeInstance, _ctx: &Context) -> BTreeMap<String, String> {
+/// Extra labels of the class and passed through instance labels never override berg labels
+pub fn namespace_labels(
+    instance: &ChallengeInstance,
+    class: &ChallengeInstanceClass,
+    ctx: &Context,
+) -> BTreeMap<String, String> {
     let mut labels = BTreeMap::new();
+    if let Some(ref metadata) = class.spec.namespace_metadata {
+        labels.extend(metadata.labels.clone());
+        if let Some(ref pod_security) = metadata.pod_security {
+            for (mode, level) in [
+                ("enforce", &pod_security.enforce),
+                ("warn", &pod_security.warn),
+                ("audit", &pod_security.audit),
+            ] {
+                if let Some(level) = level {
+                    labels.insert(
+                        format!("pod-security.kubernetes.io/{}", mode),
+                        level.as_str().to_string(),
+                    );
+                }
+            }
+        }
+    }
+    labels.extend(passthrough_labels(
+        instance,
+        ctx.config.label_passthrough_prefix.as_deref(),
+    ));
     labels.insert(
         "app.kubernetes.io/managed-by".to_string(),
         "berg".to_string(),
@@ -78,11 +105,56 @@ pub fn namespace_labels(instance: &ChallengeInstance, _ctx: &Context) -> BTreeMa
     labels
 }
 
+/// Generate annotations for namespace
+pub fn namespace_annotations(class: &ChallengeInstanceClass) -> BTreeMap<String, String> {
+    class
+        .spec
+        .namespace_metadata
+        .as_ref()
+        .map(|metadata| metadata.annotations.clone())
+        .unwrap_or_default()
+}
+
+/// Instance labels starting with the configured passthrough prefix, e.g. for cost allocation
+pub fn passthrough_labels(
+    instance: &ChallengeInstance,
+    prefix: Option<&str>,
+) -> BTreeMap<String, String> {
+    let Some(prefix) = prefix else {
+        return BTreeMap::new();
+    };
+
+    instance
+        .labels()
+        .iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Generate the labels that change when an instance claims a spare from a warm pool
+/// The instance-id label is kept since the instance adopts the instance ID of the spare
+pub fn claimed_labels(instance: &ChallengeInstance, ctx: &Context) -> BTreeMap<String, String> {
+    let mut labels = passthrough_labels(instance, ctx.config.label_passthrough_prefix.as_deref());
+    labels.insert(
+        "berg.norelect.ch/owner-id".to_string(),
+        instance.spec.owner_id.clone(),
+    );
+    labels
+}
+
+/// Label selecting the pod of a container, carrying the pod name
+pub const CONTAINER_LABEL: &str = "berg.norelect.ch/container";
+
+/// Label marking pods that only run auxiliary containers
+pub const ROLE_LABEL: &str = "berg.norelect.ch/role";
+
 /// Generate labels for pods
+/// The container label carries the pod name, which is the hostname of ungrouped containers
 pub fn pod_labels(
     instance: &ChallengeInstance,
     challenge: &Challenge,
-    container: &ContainerSpec,
+    pod: &PodGroup<'_>,
 ) -> BTreeMap<String, String> {
     let mut labels = common_labels(instance, challenge);
     // Override component to match selector
@@ -90,15 +162,15 @@ pub fn pod_labels(
         "app.kubernetes.io/component".to_string(),
         "challenge-pod".to_string(),
     );
-    labels.insert(
-        "berg.norelect.ch/container".to_string(),
-        container.hostname.clone(),
-    );
+    labels.insert(CONTAINER_LABEL.to_string(), pod.name.to_string());
+    if pod.is_auxiliary() {
+        labels.insert(ROLE_LABEL.to_string(), "auxiliary".to_string());
+    }
     labels
 }
 
 /// Generate selector labels for pods
-pub fn pod_selector_labels(container: &ContainerSpec) -> BTreeMap<String, String> {
+pub fn pod_selector_labels(pod: &str) -> BTreeMap<String, String> {
     let mut labels = BTreeMap::new();
     labels.insert(
         "app.kubernetes.io/managed-by".to_string(),
@@ -108,10 +180,7 @@ pub fn pod_selector_labels(container: &ContainerSpec) -> BTreeMap<String, String
         "app.kubernetes.io/component".to_string(),
         "challenge-pod".to_string(),
     );
-    labels.insert(
-        "berg.norelect.ch/container".to_string(),
-        container.hostname.clone(),
-    );
+    labels.insert(CONTAINER_LABEL.to_string(), pod.to_string());
     labels
 }
 
@@ -122,3 +191,25 @@ pub fn resource_labels(
 ) -> BTreeMap<String, String> {
     common_labels(instance, challenge)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passthrough_labels() {
+        let mut instance = crate::test_fixtures::instance();
+        instance.metadata.labels = Some(BTreeMap::from([
+            ("cost.example.com/team".to_string(), "red".to_string()),
+            ("app.kubernetes.io/name".to_string(), "web".to_string()),
+        ]));
+
+        let labels = passthrough_labels(&instance, Some("cost.example.com/"));
+        assert_eq!(labels.len(), 1);
+        assert_eq!(
+            labels.get("cost.example.com/team").map(String::as_str),
+            Some("red")
+        );
+        assert!(passthrough_labels(&instance, None).is_empty());
+    }
+}
@@ -1,8 +1,11 @@
 pub mod configmap;
 pub mod deployment;
 pub mod gateway;
+pub mod kubernetes_network_policy;
 pub mod labels;
 pub mod namespace;
 pub mod network_policy;
 pub mod pdb;
+pub mod pod;
 pub mod service;
+pub mod volume;
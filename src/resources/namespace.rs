@@ -1,21 +1,33 @@
 use crate::{
-    crds::ChallengeInstance,
+    crds::{ChallengeInstance, ChallengeInstanceClass, LimitRangeConfig, QuotaConfig},
     error::{Error, Result},
     reconciler::Context,
     resources::labels,
 };
-use k8s_openapi::api::core::v1::{Namespace, Secret};
+use k8s_openapi::{
+    api::core::v1::{
+        LimitRange, LimitRangeItem, LimitRangeSpec, Namespace, ResourceQuota, ResourceQuotaSpec,
+        Secret,
+    },
+    apimachinery::pkg::api::resource::Quantity,
+};
 use kube::{
-    api::{Api, PostParams},
+    api::{Api, ObjectMeta, PostParams},
     Client, Resource,
 };
+use std::collections::BTreeMap;
 use tracing::{debug, info, warn};
 
-/// reconcile attempts to create a Namespace
+/// Name of the ResourceQuota and LimitRange created in instance namespaces
+const GUARDRAIL_NAME: &str = "berg-instance";
+
+/// reconcile attempts to create a Namespace along with the ResourceQuota and LimitRange of the
+/// class
 /// if the Namespace already exists it returns OK
 pub async fn reconcile(
     instance: &ChallengeInstance,
     namespace_name: &str,
+    class: &ChallengeInstanceClass,
     ctx: &Context,
 ) -> Result<()> {
     let namespaces: Api<Namespace> = Api::all(ctx.client.clone());
@@ -23,7 +35,8 @@ pub async fn reconcile(
     let ns = Namespace {
         metadata: kube::api::ObjectMeta {
             name: Some(namespace_name.to_string()),
-            labels: Some(labels::namespace_labels(instance, ctx)),
+            labels: Some(labels::namespace_labels(instance, class, ctx)),
+            annotations: Some(labels::namespace_annotations(class)),
             owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
             ..Default::default()
         },
@@ -31,16 +44,141 @@ pub async fn reconcile(
     };
 
     match namespaces.create(&PostParams::default(), &ns).await {
-        Ok(_) => {
-            info!("Created namespace {}", namespace_name);
-            Ok(())
-        }
+        Ok(_) => info!("Created namespace {}", namespace_name),
         Err(kube::Error::Api(ae)) if ae.code == 409 => {
+            // the namespace may be left over from another instance that is still terminating
+            let existing = namespaces.get(namespace_name).await?;
+            if !is_owned_by(&existing, instance) {
+                return Err(Error::NamespaceCollision {
+                    name: namespace_name.to_string(),
+                });
+            }
             debug!("Namespace {} already exists", namespace_name);
-            Ok(())
         }
-        Err(e) => Err(Error::from(e)),
+        Err(e) => return Err(Error::from(e)),
     }
+
+    if let Some(ref quota) = class.spec.quota {
+        let api: Api<ResourceQuota> = Api::namespaced(ctx.client.clone(), namespace_name);
+        match api
+            .create(
+                &PostParams::default(),
+                &build_resource_quota(instance, quota, namespace_name),
+            )
+            .await
+        {
+            Ok(_) => info!("Created resource quota in {}", namespace_name),
+            Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                debug!("Resource quota already exists in {}", namespace_name)
+            }
+            Err(e) => return Err(Error::from(e)),
+        }
+    }
+
+    if let Some(ref limit_range) = class.spec.limit_range {
+        let api: Api<LimitRange> = Api::namespaced(ctx.client.clone(), namespace_name);
+        match api
+            .create(
+                &PostParams::default(),
+                &build_limit_range(instance, limit_range, namespace_name),
+            )
+            .await
+        {
+            Ok(_) => info!("Created limit range in {}", namespace_name),
+            Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                debug!("Limit range already exists in {}", namespace_name)
+            }
+            Err(e) => return Err(Error::from(e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the ResourceQuota bounding everything running in an instance namespace
+fn build_resource_quota(
+    instance: &ChallengeInstance,
+    quota: &QuotaConfig,
+    namespace: &str,
+) -> ResourceQuota {
+    let mut hard = BTreeMap::new();
+    if let Some(ref cpu) = quota.cpu {
+        hard.insert("limits.cpu".to_string(), Quantity(cpu.clone()));
+    }
+    if let Some(ref memory) = quota.memory {
+        hard.insert("limits.memory".to_string(), Quantity(memory.clone()));
+    }
+    if let Some(pods) = quota.pods {
+        hard.insert("pods".to_string(), Quantity(pods.to_string()));
+    }
+    if let Some(services) = quota.services {
+        hard.insert("services".to_string(), Quantity(services.to_string()));
+    }
+    if let Some(ref ephemeral_storage) = quota.ephemeral_storage {
+        hard.insert(
+            "requests.ephemeral-storage".to_string(),
+            Quantity(ephemeral_storage.clone()),
+        );
+    }
+
+    ResourceQuota {
+        metadata: ObjectMeta {
+            name: Some(GUARDRAIL_NAME.to_string()),
+            namespace: Some(namespace.to_string()),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        spec: Some(ResourceQuotaSpec {
+            hard: Some(hard),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Build the LimitRange constraining every container in an instance namespace
+fn build_limit_range(
+    instance: &ChallengeInstance,
+    limit_range: &LimitRangeConfig,
+    namespace: &str,
+) -> LimitRange {
+    let quantities = |values: &BTreeMap<String, String>| {
+        (!values.is_empty()).then(|| {
+            values
+                .iter()
+                .map(|(resource, value)| (resource.clone(), Quantity(value.clone())))
+                .collect()
+        })
+    };
+
+    LimitRange {
+        metadata: ObjectMeta {
+            name: Some(GUARDRAIL_NAME.to_string()),
+            namespace: Some(namespace.to_string()),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        spec: Some(LimitRangeSpec {
+            limits: vec![LimitRangeItem {
+                type_: "Container".to_string(),
+                default: quantities(&limit_range.default),
+                default_request: quantities(&limit_range.default_request),
+                max: quantities(&limit_range.max),
+                min: quantities(&limit_range.min),
+                ..Default::default()
+            }],
+        }),
+    }
+}
+
+/// Check if a namespace was created for, or handed over to, the given instance
+pub fn is_owned_by(namespace: &Namespace, instance: &ChallengeInstance) -> bool {
+    namespace
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|r| Some(&r.uid) == instance.meta().uid.as_ref())
 }
 
 pub async fn copy_pull_secret(
@@ -82,3 +220,33 @@ pub async fn copy_pull_secret(
         Err(e) => Err(Error::from(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_resource_quota() {
+        let instance = crate::test_fixtures::instance();
+        let quota = QuotaConfig {
+            cpu: Some("2".to_string()),
+            pods: Some(4),
+            ephemeral_storage: Some("1Gi".to_string()),
+            ..Default::default()
+        };
+
+        let hard = build_resource_quota(&instance, &quota, "ci-web")
+            .spec
+            .unwrap()
+            .hard
+            .unwrap();
+
+        assert_eq!(hard.get("limits.cpu"), Some(&Quantity("2".to_string())));
+        assert_eq!(hard.get("pods"), Some(&Quantity("4".to_string())));
+        assert_eq!(
+            hard.get("requests.ephemeral-storage"),
+            Some(&Quantity("1Gi".to_string()))
+        );
+        assert!(!hard.contains_key("limits.memory"));
+    }
+}
@@ -1,111 +1,255 @@
 use crate::{
     crds::{
         cilium::{
-            entities, CiliumDnsRule, CiliumEgressRule, CiliumL7Rule, CiliumPortProtocol,
-            CiliumPortRule,
+            entities, CiliumDnsRule, CiliumEgressRule, CiliumFQDNRule, CiliumIngressRule,
+            CiliumL7Rule, CiliumPortProtocol, CiliumPortRule,
         },
         Challenge, ChallengeInstance, ChallengeInstanceClass, CiliumNetworkPolicy,
-        CiliumNetworkPolicySpec,
+        CiliumNetworkPolicySpec, EgressSpec, NetworkPolicyBackend, ServiceEndpoint,
+    },
+    error::Result,
+    reconciler::{Context, FIELD_MANAGER},
+    resources::{
+        kubernetes_network_policy,
+        labels::{CONTAINER_LABEL, ROLE_LABEL},
+        pod::{self, PodGroup},
     },
-    error::{Error, Result},
-    reconciler::Context,
 };
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
 use kube::{
-    api::{Api, PostParams},
-    Resource,
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams},
+    Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
 };
-use std::collections::BTreeMap;
 use tracing::{debug, info};
 
-/// reconcile attempts to create a CiliumNetworkPolicy for the challenge instance
-/// if a CiliumNetworkPolicy with that name already exists it returns Ok
-/// it does not attempt to mutate existing policies
+/// reconcile creates the network policies of the challenge instance with the backend of its class
+/// Returns the rules the backend cannot express
 pub async fn reconcile(
     instance: &ChallengeInstance,
     challenge: &Challenge,
     namespace: &str,
     class: &ChallengeInstanceClass,
+    endpoints: &[ServiceEndpoint],
+    ctx: &Context,
+) -> Result<Vec<String>> {
+    let backend = class
+        .spec
+        .network
+        .as_ref()
+        .map(|n| n.policy_backend.clone())
+        .unwrap_or_default();
+
+    match backend {
+        NetworkPolicyBackend::Cilium => {
+            reconcile_cilium(instance, challenge, namespace, class, endpoints, ctx).await?;
+            Ok(Vec::new())
+        }
+        NetworkPolicyBackend::Kubernetes => {
+            kubernetes_network_policy::reconcile(instance, challenge, namespace, class, ctx).await
+        }
+    }
+}
+
+/// reconcile_cilium applies the CiliumNetworkPolicies of the challenge instance
+/// server-side apply keeps existing policies in line with the current rules
+async fn reconcile_cilium(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+    endpoints: &[ServiceEndpoint],
     ctx: &Context,
 ) -> Result<()> {
     let api: Api<CiliumNetworkPolicy> = Api::namespaced(ctx.client.clone(), namespace);
 
-    let mut egress_rules = vec![
+    let policies = build_policies(instance, challenge, namespace, class, endpoints);
+    for policy in &policies {
+        let name = policy.metadata.name.clone().unwrap_or_default();
+        api.patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(policy),
+        )
+        .await?;
+        debug!("Applied CiliumNetworkPolicy {} in {}", name, namespace);
+    }
+
+    delete_stale(&api, &policies, namespace).await
+}
+
+/// Selector of the policies berg created in an instance namespace
+pub const POLICY_SELECTOR: &str =
+    "app.kubernetes.io/managed-by=berg,app.kubernetes.io/component=network-policy";
+
+/// Names of existing policies the current rules no longer produce
+/// e.g. of a restricted container whose allowedPeers were removed by a challenge update
+pub fn stale_names<'a>(existing: &'a [String], desired: &[String]) -> Vec<&'a str> {
+    existing
+        .iter()
+        .filter(|name| !desired.contains(name))
+        .map(String::as_str)
+        .collect()
+}
+
+/// Delete the policies of an instance namespace that are not in the given set
+pub async fn delete_stale<K>(api: &Api<K>, policies: &[K], namespace: &str) -> Result<()>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+{
+    let desired = policies.iter().map(|p| p.name_any()).collect::<Vec<_>>();
+    let existing = api
+        .list(&ListParams::default().labels(POLICY_SELECTOR))
+        .await?
+        .iter()
+        .map(|p| p.name_any())
+        .collect::<Vec<_>>();
+
+    for name in stale_names(&existing, &desired) {
+        match api.delete(name, &DeleteParams::default()).await {
+            Ok(_) => info!("Deleted stale network policy {} in {}", name, namespace),
+            Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the policies of an instance
+/// Cilium policies are additive, so the challenge policy must not select auxiliary pods
+/// or pods whose peers are restricted
+fn build_policies(
+    instance: &ChallengeInstance,
+    challenge: &Challenge,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+    endpoints: &[ServiceEndpoint],
+) -> Vec<CiliumNetworkPolicy> {
+    let egress_rules = |peer_rules: Vec<CiliumEgressRule>| {
         // Rule 1: Allow DNS to kube-dns
-        CiliumEgressRule {
-            to_endpoints: Some(vec![LabelSelector {
-                match_labels: Some({
-                    let mut labels = BTreeMap::new();
-                    labels.insert(
-                        "k8s:io.kubernetes.pod.namespace".to_string(),
-                        "kube-system".to_string(),
-                    );
-                    labels.insert("k8s:k8s-app".to_string(), "kube-dns".to_string());
-                    labels
-                }),
+        // If outbound traffic is forbidden, only accept DNS requests for internal services
+        // and the allowed hosts, which also lets Cilium learn the addresses behind them
+        let mut rules = vec![dns_rule((!challenge.spec.allow_outbound_traffic).then(
+            || {
+                std::iter::once(dns_pattern(service_pattern(namespace)))
+                    .chain(challenge.spec.allowed_egress.iter().map(egress_dns_rule))
+                    .collect()
+            },
+        ))];
+        // Rule 2: Allow traffic to other pods in the same namespace, or only to the peers
+        rules.extend(peer_rules);
+        // Rule 3: Allow outbound traffic to host (for OIDC callbacks)
+        rules.push(gateway_rule(class));
+
+        // Rule 4: If allow_outbound_traffic is true, allow all traffic to world
+        // otherwise only to the allowed hosts
+        if challenge.spec.allow_outbound_traffic {
+            rules.push(CiliumEgressRule {
+                to_endpoints: None,
+                to_entities: Some(vec![entities::WORLD.to_string()]),
+                to_fqd_ns: None,
+                to_ports: None,
+            });
+        } else {
+            rules.extend(challenge.spec.allowed_egress.iter().map(egress_rule));
+        }
+        rules
+    };
+
+    let groups = pod::groups(challenge);
+    let restricted = restricted_pods(&groups);
+
+    // Only the instance itself, the gateway and NodePort traffic may reach challenge pods,
+    // so other instances cannot connect by ClusterIP
+    let ingress_rules = vec![
+        same_namespace_ingress_rule(),
+        // Cilium's Gateway API implementation forwards with the reserved ingress identity
+        CiliumIngressRule {
+            from_endpoints: Some(vec![LabelSelector {
+                match_labels: Some(BTreeMap::from([(
+                    "k8s:io.kubernetes.pod.namespace".to_string(),
+                    class.spec.gateway.namespace.clone(),
+                )])),
                 ..Default::default()
             }]),
-            to_entities: None,
-            to_fqd_ns: None,
-            to_ports: Some(vec![CiliumPortRule {
-                ports: Some(vec![CiliumPortProtocol {
-                    port: Some("53".to_string()),
-                    protocol: None,
-                }]),
-                rules: if challenge.spec.allow_outbound_traffic {
-                    None
-                } else {
-                    // If outbound traffic is forbidden, only accept DNS requests for internal services
-                    Some(CiliumL7Rule {
-                        dns: Some(vec![CiliumDnsRule {
-                            match_name: None,
-                            match_pattern: Some(format!("*.{}.svc.cluster.local.", namespace)),
-                        }]),
-                    })
-                },
-            }]),
-        },
-        // Rule 2: Allow traffic to other pods in the same namespace
-        CiliumEgressRule {
-            to_endpoints: Some(vec![LabelSelector::default()]), // Empty selector matches all
-            to_entities: None,
-            to_fqd_ns: None,
+            from_entities: None,
             to_ports: None,
         },
-        // Rule 3: Allow outbound traffic to host (for OIDC callbacks)
-        CiliumEgressRule {
-            to_endpoints: None,
-            to_entities: Some(vec![entities::HOST.to_string()]),
-            to_fqd_ns: None,
-            to_ports: Some(vec![CiliumPortRule {
-                ports: Some(vec![
-                    CiliumPortProtocol {
-                        port: Some(class.spec.gateway.http_port.to_string()),
-                        protocol: None,
-                    },
-                    CiliumPortProtocol {
-                        port: Some(class.spec.gateway.tls_port.to_string()),
-                        protocol: None,
-                    },
-                ]),
-                rules: None,
-            }]),
+        CiliumIngressRule {
+            from_endpoints: None,
+            from_entities: Some(vec![
+                entities::INGRESS.to_string(),
+                entities::HOST.to_string(),
+                entities::REMOTE_NODE.to_string(),
+            ]),
+            to_ports: None,
         },
     ];
 
-    // Rule 4: If allow_outbound_traffic is true, allow all traffic to world
-    if challenge.spec.allow_outbound_traffic {
-        egress_rules.push(CiliumEgressRule {
-            to_endpoints: None,
-            to_entities: Some(vec![entities::WORLD.to_string()]),
-            to_fqd_ns: None,
-            to_ports: None,
-        });
+    let mut policies = vec![build_policy(
+        instance,
+        "challenge-network-policy",
+        namespace,
+        challenge_selector(&restricted),
+        ingress_rules.clone(),
+        egress_rules(vec![namespace_rule()]),
+    )];
+
+    for pod in restricted {
+        policies.push(build_policy(
+            instance,
+            &format!("container-{}-network-policy", pod.name),
+            namespace,
+            container_selector(pod.name),
+            ingress_rules.clone(),
+            egress_rules(peer_rules(challenge, pod)),
+        ));
+    }
+
+    // Auxiliary pods, e.g. admin bots, may only reach the instance, directly or via the gateway
+    // they only resolve the hostnames of this instance, so they cannot be aimed at other teams
+    if groups.iter().any(|p| p.is_auxiliary()) {
+        let hostnames = endpoints
+            .iter()
+            .map(|e| e.hostname.as_str())
+            .collect::<BTreeSet<_>>();
+        policies.push(build_policy(
+            instance,
+            "auxiliary-network-policy",
+            namespace,
+            auxiliary_selector(),
+            vec![same_namespace_ingress_rule()],
+            vec![
+                dns_rule(Some(
+                    std::iter::once(dns_pattern(service_pattern(namespace)))
+                        .chain(hostnames.into_iter().map(|h| dns_name(h.to_string())))
+                        .collect(),
+                )),
+                namespace_rule(),
+                gateway_rule(class),
+            ],
+        ));
     }
 
-    let policy = CiliumNetworkPolicy {
+    policies
+}
+
+fn build_policy(
+    instance: &ChallengeInstance,
+    name: &str,
+    namespace: &str,
+    endpoint_selector: LabelSelector,
+    ingress_rules: Vec<CiliumIngressRule>,
+    egress_rules: Vec<CiliumEgressRule>,
+) -> CiliumNetworkPolicy {
+    CiliumNetworkPolicy {
         metadata: kube::api::ObjectMeta {
-            name: Some("challenge-network-policy".to_string()),
+            name: Some(name.to_string()),
             namespace: Some(namespace.to_string()),
             owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
             labels: Some({
@@ -123,20 +267,488 @@ pub async fn reconcile(
             ..Default::default()
         },
         spec: CiliumNetworkPolicySpec {
-            endpoint_selector: Some(LabelSelector::default()), // Empty selector matches all pods in namespace
+            endpoint_selector: Some(endpoint_selector),
+            ingress: Some(ingress_rules),
             egress: Some(egress_rules),
         },
-    };
+    }
+}
+
+/// Pods with a container declaring its peers, which only reach those peers
+pub fn restricted_pods<'a, 'b>(groups: &'b [PodGroup<'a>]) -> Vec<&'b PodGroup<'a>> {
+    groups
+        .iter()
+        .filter(|p| !p.is_auxiliary())
+        .filter(|p| p.containers.iter().any(|c| c.allowed_peers.is_some()))
+        .collect()
+}
+
+/// Select the challenge pods which are neither auxiliary nor restricted
+pub fn challenge_selector(restricted: &[&PodGroup<'_>]) -> LabelSelector {
+    let mut selector = vec![LabelSelectorRequirement {
+        key: ROLE_LABEL.to_string(),
+        operator: "DoesNotExist".to_string(),
+        values: None,
+    }];
+    if !restricted.is_empty() {
+        selector.push(LabelSelectorRequirement {
+            key: CONTAINER_LABEL.to_string(),
+            operator: "NotIn".to_string(),
+            values: Some(restricted.iter().map(|p| p.name.to_string()).collect()),
+        });
+    }
+
+    LabelSelector {
+        match_expressions: Some(selector),
+        ..Default::default()
+    }
+}
+
+/// Select the auxiliary pods
+pub fn auxiliary_selector() -> LabelSelector {
+    LabelSelector {
+        match_labels: Some(BTreeMap::from([(
+            ROLE_LABEL.to_string(),
+            "auxiliary".to_string(),
+        )])),
+        ..Default::default()
+    }
+}
+
+/// Find an allowed peer that is not a container of the challenge
+/// Returns the container declaring it and the unknown hostname
+pub fn unknown_peer(challenge: &Challenge) -> Option<(&str, &str)> {
+    let containers = &challenge.spec.containers;
+    containers.iter().find_map(|container| {
+        container
+            .allowed_peers
+            .iter()
+            .flatten()
+            .find(|peer| !containers.iter().any(|c| c.hostname == peer.hostname))
+            .map(|peer| (container.hostname.as_str(), peer.hostname.as_str()))
+    })
+}
+
+/// Pod a peer is reached through
+pub fn peer_pod<'a>(challenge: &'a Challenge, hostname: &'a str) -> &'a str {
+    challenge
+        .spec
+        .containers
+        .iter()
+        .find(|c| c.hostname == hostname)
+        .map(|c| c.pod_name())
+        .unwrap_or(hostname)
+}
+
+/// Select the pod of a container by the container label
+pub fn container_selector(pod: &str) -> LabelSelector {
+    LabelSelector {
+        match_labels: Some(BTreeMap::from([(
+            CONTAINER_LABEL.to_string(),
+            pod.to_string(),
+        )])),
+        ..Default::default()
+    }
+}
+
+/// Traffic to the declared peers of the containers of a pod
+/// Containers of the same pod share the network, so their peers are merged
+fn peer_rules(challenge: &Challenge, pod: &PodGroup<'_>) -> Vec<CiliumEgressRule> {
+    pod.containers
+        .iter()
+        .flat_map(|c| c.allowed_peers.iter().flatten())
+        .map(|peer| CiliumEgressRule {
+            to_endpoints: Some(vec![container_selector(peer_pod(
+                challenge,
+                &peer.hostname,
+            ))]),
+            to_entities: None,
+            to_fqd_ns: None,
+            to_ports: port_rules(&peer.ports),
+        })
+        .collect()
+}
+
+/// Traffic from the other pods in the instance namespace
+fn same_namespace_ingress_rule() -> CiliumIngressRule {
+    CiliumIngressRule {
+        from_endpoints: Some(vec![LabelSelector::default()]), // Empty selector matches all
+        from_entities: None,
+        to_ports: None,
+    }
+}
+
+/// Restrict a rule to the given ports, all ports if empty
+fn port_rules(ports: &[u16]) -> Option<Vec<CiliumPortRule>> {
+    if ports.is_empty() {
+        return None;
+    }
 
-    match api.create(&PostParams::default(), &policy).await {
-        Ok(_) => {
-            info!("Created CiliumNetworkPolicy in {}", namespace);
-            Ok(())
+    Some(vec![CiliumPortRule {
+        ports: Some(
+            ports
+                .iter()
+                .map(|port| CiliumPortProtocol {
+                    port: Some(port.to_string()),
+                    protocol: None,
+                })
+                .collect(),
+        ),
+        rules: None,
+    }])
+}
+
+/// Traffic to an allowed host outside the cluster
+fn egress_rule(egress: &EgressSpec) -> CiliumEgressRule {
+    let fqdn = if egress.fqdn.contains('*') {
+        CiliumFQDNRule {
+            match_name: None,
+            match_pattern: Some(egress.fqdn.clone()),
         }
-        Err(kube::Error::Api(ae)) if ae.code == 409 => {
-            debug!("CiliumNetworkPolicy already exists in {}", namespace);
-            Ok(())
+    } else {
+        CiliumFQDNRule {
+            match_name: Some(egress.fqdn.clone()),
+            match_pattern: None,
         }
-        Err(e) => Err(Error::from(e)),
+    };
+
+    CiliumEgressRule {
+        to_endpoints: None,
+        to_entities: None,
+        to_fqd_ns: Some(vec![fqdn]),
+        to_ports: port_rules(&egress.ports),
+    }
+}
+
+/// DNS lookups of an allowed host outside the cluster
+fn egress_dns_rule(egress: &EgressSpec) -> CiliumDnsRule {
+    if egress.fqdn.contains('*') {
+        dns_pattern(egress.fqdn.clone())
+    } else {
+        dns_name(egress.fqdn.clone())
+    }
+}
+
+/// DNS pattern matching the services of the instance namespace
+fn service_pattern(namespace: &str) -> String {
+    format!("*.{}.svc.cluster.local.", namespace)
+}
+
+fn dns_pattern(pattern: String) -> CiliumDnsRule {
+    CiliumDnsRule {
+        match_name: None,
+        match_pattern: Some(pattern),
+    }
+}
+
+fn dns_name(name: String) -> CiliumDnsRule {
+    CiliumDnsRule {
+        match_name: Some(name),
+        match_pattern: None,
+    }
+}
+
+/// DNS to kube-dns, restricted to the given names if any
+fn dns_rule(names: Option<Vec<CiliumDnsRule>>) -> CiliumEgressRule {
+    CiliumEgressRule {
+        to_endpoints: Some(vec![LabelSelector {
+            match_labels: Some({
+                let mut labels = BTreeMap::new();
+                labels.insert(
+                    "k8s:io.kubernetes.pod.namespace".to_string(),
+                    "kube-system".to_string(),
+                );
+                labels.insert("k8s:k8s-app".to_string(), "kube-dns".to_string());
+                labels
+            }),
+            ..Default::default()
+        }]),
+        to_entities: None,
+        to_fqd_ns: None,
+        to_ports: Some(vec![CiliumPortRule {
+            ports: Some(vec![CiliumPortProtocol {
+                port: Some("53".to_string()),
+                protocol: None,
+            }]),
+            rules: names.map(|names| CiliumL7Rule { dns: Some(names) }),
+        }]),
+    }
+}
+
+/// Traffic to the other pods in the instance namespace
+fn namespace_rule() -> CiliumEgressRule {
+    CiliumEgressRule {
+        to_endpoints: Some(vec![LabelSelector::default()]), // Empty selector matches all
+        to_entities: None,
+        to_fqd_ns: None,
+        to_ports: None,
+    }
+}
+
+/// Traffic to the gateway ports on the host
+fn gateway_rule(class: &ChallengeInstanceClass) -> CiliumEgressRule {
+    CiliumEgressRule {
+        to_endpoints: None,
+        to_entities: Some(vec![entities::HOST.to_string()]),
+        to_fqd_ns: None,
+        to_ports: Some(vec![CiliumPortRule {
+            ports: Some(vec![
+                CiliumPortProtocol {
+                    port: Some(class.spec.gateway.http_port.to_string()),
+                    protocol: None,
+                },
+                CiliumPortProtocol {
+                    port: Some(class.spec.gateway.tls_port.to_string()),
+                    protocol: None,
+                },
+            ]),
+            rules: None,
+        }]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::instance;
+    use serde_json::json;
+
+    fn challenge(containers: serde_json::Value) -> Challenge {
+        crate::test_fixtures::challenge(json!({
+            "allowOutboundTraffic": true,
+            "containers": containers
+        }))
+    }
+
+    fn class() -> ChallengeInstanceClass {
+        crate::test_fixtures::class(json!({}))
+    }
+
+    #[test]
+    fn test_ingress_isolation() {
+        let challenge = challenge(json!([{ "hostname": "web", "image": "web" }]));
+
+        let policies = build_policies(&instance(), &challenge, "instance-ns", &class(), &[]);
+        assert_eq!(policies.len(), 1);
+
+        // server-side apply needs the type of the object
+        let applied = serde_json::to_value(&policies[0]).unwrap();
+        assert_eq!(applied["apiVersion"], "cilium.io/v2");
+        assert_eq!(applied["kind"], "CiliumNetworkPolicy");
+
+        let ingress = policies[0].spec.ingress.as_ref().unwrap();
+        assert_eq!(
+            ingress[0].from_endpoints,
+            Some(vec![LabelSelector::default()])
+        );
+        assert_eq!(
+            ingress[1].from_endpoints.as_ref().unwrap()[0]
+                .match_labels
+                .as_ref()
+                .unwrap()["k8s:io.kubernetes.pod.namespace"],
+            "berg"
+        );
+        let entities = ingress[2].from_entities.as_ref().unwrap();
+        assert!(entities.contains(&entities::HOST.to_string()));
+        assert!(entities.contains(&entities::REMOTE_NODE.to_string()));
+        assert!(!entities.contains(&entities::WORLD.to_string()));
+    }
+
+    #[test]
+    fn test_auxiliary_policy() {
+        let challenge = challenge(json!([
+            { "hostname": "web", "image": "web" },
+            {
+                "hostname": "bot",
+                "image": "bot",
+                "role": "bot",
+                "dynamicFlag": { "cookie": { "name": "flag", "domain": "web" } }
+            }
+        ]));
+
+        let endpoints: Vec<ServiceEndpoint> = serde_json::from_value(json!([
+            { "name": "web:80", "hostname": "abc.chall.example.com", "port": 443, "protocol": "TCP" }
+        ]))
+        .unwrap();
+        let policies = build_policies(&instance(), &challenge, "instance-ns", &class(), &endpoints);
+        assert_eq!(policies.len(), 2);
+
+        // the bot must never reach the world, even if the challenge may
+        let auxiliary = &policies[1].spec;
+        let egress = auxiliary.egress.as_ref().unwrap();
+        assert!(!egress.iter().any(|rule| rule
+            .to_entities
+            .as_ref()
+            .is_some_and(|e| e.contains(&entities::WORLD.to_string()))));
+        // nor resolve the routes of other instances
+        let dns = egress[0].to_ports.as_ref().unwrap()[0]
+            .rules
+            .as_ref()
+            .unwrap()
+            .dns
+            .as_ref()
+            .unwrap();
+        assert_eq!(dns.len(), 2);
+        assert_eq!(dns[1].match_name.as_deref(), Some("abc.chall.example.com"));
+        assert!(dns.iter().all(|d| d
+            .match_pattern
+            .as_deref()
+            .is_none_or(|p| !p.contains("chall.example.com"))));
+        assert_eq!(
+            auxiliary.endpoint_selector.as_ref().unwrap().match_labels,
+            Some(BTreeMap::from([(
+                ROLE_LABEL.to_string(),
+                "auxiliary".to_string()
+            )]))
+        );
+        // and is not reachable from outside the instance
+        assert_eq!(auxiliary.ingress.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_peer() {
+        let mut challenge = challenge(json!([
+            { "hostname": "frontend", "image": "frontend", "allowedPeers": [{ "hostname": "db" }] },
+            { "hostname": "db", "image": "postgres" }
+        ]));
+        assert_eq!(unknown_peer(&challenge), None);
+
+        challenge.spec.containers[0].allowed_peers.as_mut().unwrap()[0].hostname =
+            "database".to_string();
+        assert_eq!(unknown_peer(&challenge), Some(("frontend", "database")));
+    }
+
+    #[test]
+    fn test_stale_policies() {
+        let restricted = challenge(json!([
+            { "hostname": "web", "image": "web", "allowedPeers": [] },
+            { "hostname": "bot", "image": "bot", "role": "auxiliary" }
+        ]));
+        let open = challenge(json!([{ "hostname": "web", "image": "web" }]));
+        let names = |challenge: &Challenge| {
+            build_policies(&instance(), challenge, "instance-ns", &class(), &[])
+                .iter()
+                .map(|p| p.name_any())
+                .collect::<Vec<_>>()
+        };
+
+        let existing = names(&restricted);
+        assert_eq!(
+            existing,
+            vec![
+                "challenge-network-policy",
+                "container-web-network-policy",
+                "auxiliary-network-policy"
+            ]
+        );
+        // the update dropped the peers and the bot, so their policies must go
+        assert_eq!(
+            stale_names(&existing, &names(&open)),
+            vec!["container-web-network-policy", "auxiliary-network-policy"]
+        );
+    }
+
+    #[test]
+    fn test_allowed_peers() {
+        let challenge = challenge(json!([
+            {
+                "hostname": "frontend",
+                "image": "frontend",
+                "allowedPeers": [{ "hostname": "db", "ports": [5432] }]
+            },
+            { "hostname": "vuln", "image": "vuln", "allowedPeers": [] },
+            { "hostname": "db", "image": "postgres" }
+        ]));
+
+        let policies = build_policies(&instance(), &challenge, "instance-ns", &class(), &[]);
+        assert_eq!(policies.len(), 3);
+
+        // the namespace-wide rule must not apply to restricted containers
+        let expressions = policies[0]
+            .spec
+            .endpoint_selector
+            .as_ref()
+            .unwrap()
+            .match_expressions
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            expressions[1].values,
+            Some(vec!["frontend".to_string(), "vuln".to_string()])
+        );
+
+        let frontend = policies[1].spec.egress.as_ref().unwrap();
+        assert!(!frontend
+            .iter()
+            .any(|rule| rule.to_endpoints == Some(vec![LabelSelector::default()])));
+        let db = frontend
+            .iter()
+            .find(|rule| rule.to_endpoints == Some(vec![container_selector("db")]))
+            .unwrap();
+        assert_eq!(
+            db.to_ports.as_ref().unwrap()[0].ports.as_ref().unwrap()[0].port,
+            Some("5432".to_string())
+        );
+
+        // an empty peer list leaves only DNS, the gateway and the world
+        let vuln = policies[2].spec.egress.as_ref().unwrap();
+        assert!(!vuln
+            .iter()
+            .flat_map(|rule| rule.to_endpoints.iter().flatten())
+            .any(|selector| selector.match_labels.is_none()
+                || selector
+                    .match_labels
+                    .as_ref()
+                    .unwrap()
+                    .contains_key(CONTAINER_LABEL)));
+    }
+
+    #[test]
+    fn test_allowed_egress() {
+        let mut challenge = challenge(json!([{ "hostname": "web", "image": "web" }]));
+        challenge.spec.allow_outbound_traffic = false;
+        challenge.spec.allowed_egress = serde_json::from_value(json!([
+            { "fqdn": "api.github.com", "ports": [443] },
+            { "fqdn": "*.example.org" }
+        ]))
+        .unwrap();
+
+        let policies = build_policies(&instance(), &challenge, "instance-ns", &class(), &[]);
+        let egress = policies[0].spec.egress.as_ref().unwrap();
+
+        let dns = egress[0].to_ports.as_ref().unwrap()[0]
+            .rules
+            .as_ref()
+            .unwrap()
+            .dns
+            .as_ref()
+            .unwrap();
+        assert_eq!(dns.len(), 3);
+        assert_eq!(dns[1].match_name.as_deref(), Some("api.github.com"));
+        assert_eq!(dns[2].match_pattern.as_deref(), Some("*.example.org"));
+
+        let fqdn_rules = egress
+            .iter()
+            .filter(|rule| rule.to_fqd_ns.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(fqdn_rules.len(), 2);
+        assert_eq!(
+            fqdn_rules[0].to_ports.as_ref().unwrap()[0]
+                .ports
+                .as_ref()
+                .unwrap()[0]
+                .port
+                .as_deref(),
+            Some("443")
+        );
+        assert!(fqdn_rules[1].to_ports.is_none());
+        assert!(serde_json::to_value(fqdn_rules[0])
+            .unwrap()
+            .get("toFQDNs")
+            .is_some());
+        assert!(!egress.iter().any(|rule| rule
+            .to_entities
+            .as_ref()
+            .is_some_and(|e| e.contains(&entities::WORLD.to_string()))));
     }
 }
@@ -1,8 +1,4 @@
-use crate::{
-    crds::{ChallengeInstance, ContainerSpec},
-    error::Result,
-    reconciler::Context,
-};
+use crate::{crds::ChallengeInstance, error::Result, reconciler::Context};
 use k8s_openapi::{
     api::policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
     apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString},
@@ -14,17 +10,17 @@ use kube::{
 use std::collections::BTreeMap;
 use tracing::{debug, info};
 
-/// reconcile attempts to create pdbs for the given workload
+/// reconcile attempts to create pdbs for the given pod
 /// if the pdb already exists it returns Ok without attempting to mutate the object
 pub async fn reconcile(
     instance: &ChallengeInstance,
-    container: &ContainerSpec,
+    pod: &str,
     namespace: &str,
     ctx: &Context,
 ) -> Result<()> {
     let api: Api<PodDisruptionBudget> = Api::namespaced(ctx.client.clone(), namespace);
 
-    let pdb_name = format!("{}-pdb", container.hostname);
+    let pdb_name = format!("{}-pdb", pod);
 
     let pdb = PodDisruptionBudget {
         metadata: kube::api::ObjectMeta {
@@ -38,10 +34,7 @@ pub async fn reconcile(
             selector: Some(LabelSelector {
                 match_labels: Some({
                     let mut labels = BTreeMap::new();
-                    labels.insert(
-                        "berg.norelect.ch/container".to_string(),
-                        container.hostname.clone(),
-                    );
+                    labels.insert("berg.norelect.ch/container".to_string(), pod.to_string());
                     labels
                 }),
                 ..Default::default()
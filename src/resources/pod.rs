@@ -0,0 +1,176 @@
+use crate::crds::{Challenge, ContainerSpec};
+use std::collections::BTreeSet;
+
+/// Containers of a challenge that run together in one pod
+pub struct PodGroup<'a> {
+    pub name: &'a str,
+    pub containers: Vec<&'a ContainerSpec>,
+}
+
+impl PodGroup<'_> {
+    /// Whether the pod only runs auxiliary containers
+    /// An auxiliary container sharing a pod with a challenge container gets the network of the pod
+    pub fn is_auxiliary(&self) -> bool {
+        self.containers.iter().all(|c| c.is_auxiliary())
+    }
+
+    /// Whether any container of the pod asks for a shared process namespace
+    pub fn share_process_namespace(&self) -> bool {
+        self.containers.iter().any(|c| c.share_process_namespace)
+    }
+}
+
+/// Group the containers of a challenge by pod, in order of first appearance
+pub fn groups(challenge: &Challenge) -> Vec<PodGroup<'_>> {
+    let mut groups: Vec<PodGroup> = vec![];
+    for container in &challenge.spec.containers {
+        match groups.iter_mut().find(|g| g.name == container.pod_name()) {
+            Some(group) => group.containers.push(container),
+            None => groups.push(PodGroup {
+                name: container.pod_name(),
+                containers: vec![container],
+            }),
+        }
+    }
+    groups
+}
+
+/// Find a conflict between the containers of a pod group
+/// Containers and init containers share one namespace of names, and all of them share the ports
+/// and the pod-scoped security context of the pod
+pub fn validate(group: &PodGroup) -> Option<String> {
+    let mut names = BTreeSet::new();
+    let mut ports = BTreeSet::new();
+    for container in &group.containers {
+        let container_names = std::iter::once(&container.hostname)
+            .chain(container.init_containers.iter().map(|i| &i.name));
+        for name in container_names {
+            if !names.insert(name.as_str()) {
+                return Some(format!(
+                    "Container name {} is used twice in pod {}",
+                    name, group.name
+                ));
+            }
+        }
+
+        for port in &container.ports {
+            let protocol = port.protocol.to_uppercase();
+            if !ports.insert((port.port, protocol.clone())) {
+                return Some(format!(
+                    "Port {}/{} is exposed twice in pod {}",
+                    port.port, protocol, group.name
+                ));
+            }
+        }
+    }
+
+    disagreeing_pod_field(group)
+        .map(|field| format!("Containers of pod {} set different {}", group.name, field))
+}
+
+/// Name of a pod-scoped security context field the containers of a pod set differently
+fn disagreeing_pod_field(group: &PodGroup) -> Option<&'static str> {
+    let overrides = group
+        .containers
+        .iter()
+        .filter_map(|c| c.security_context.as_ref())
+        .collect::<Vec<_>>();
+    let fs_groups = overrides
+        .iter()
+        .filter_map(|o| o.fs_group)
+        .collect::<BTreeSet<_>>();
+    let supplemental_groups = overrides
+        .iter()
+        .filter_map(|o| o.supplemental_groups.as_ref())
+        .collect::<BTreeSet<_>>();
+    if fs_groups.len() > 1 {
+        Some("fsGroup")
+    } else if supplemental_groups.len() > 1 {
+        Some("supplementalGroups")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_groups() {
+        let challenge = crate::test_fixtures::challenge(json!({
+            "containers": [
+                { "hostname": "app", "image": "app", "pod": "web" },
+                { "hostname": "db", "image": "postgres" },
+                { "hostname": "admin", "image": "admin", "pod": "web", "shareProcessNamespace": true }
+            ]
+        }));
+
+        let groups = groups(&challenge);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "web");
+        assert_eq!(
+            groups[0]
+                .containers
+                .iter()
+                .map(|c| c.hostname.as_str())
+                .collect::<Vec<_>>(),
+            vec!["app", "admin"]
+        );
+        assert!(groups[0].share_process_namespace());
+        assert_eq!(groups[1].name, "db");
+        assert!(!groups[1].share_process_namespace());
+    }
+
+    #[test]
+    fn test_validate() {
+        let containers: Vec<ContainerSpec> = serde_json::from_value(json!([
+            {
+                "hostname": "app",
+                "image": "app",
+                "pod": "web",
+                "ports": [{ "port": 8080, "protocol": "tcp" }],
+                "initContainers": [{ "name": "migrate" }]
+            },
+            {
+                "hostname": "admin",
+                "image": "admin",
+                "pod": "web",
+                "ports": [{ "port": 8081, "protocol": "TCP" }]
+            }
+        ]))
+        .unwrap();
+        let validate_pod = |containers: &[ContainerSpec]| {
+            validate(&PodGroup {
+                name: "web",
+                containers: containers.iter().collect(),
+            })
+        };
+        assert_eq!(validate_pod(&containers), None);
+
+        let mut duplicate_init = containers.clone();
+        duplicate_init[1].init_containers = duplicate_init[0].init_containers.clone();
+        assert_eq!(
+            validate_pod(&duplicate_init).as_deref(),
+            Some("Container name migrate is used twice in pod web")
+        );
+
+        let mut duplicate_port = containers.clone();
+        duplicate_port[1].ports[0].port = 8080;
+        assert_eq!(
+            validate_pod(&duplicate_port).as_deref(),
+            Some("Port 8080/TCP is exposed twice in pod web")
+        );
+
+        let mut different_fs_group = containers.clone();
+        different_fs_group[0].security_context =
+            serde_json::from_value(json!({ "fsGroup": 1000 })).unwrap();
+        different_fs_group[1].security_context =
+            serde_json::from_value(json!({ "fsGroup": 2000, "runAsUser": 0 })).unwrap();
+        assert_eq!(
+            validate_pod(&different_fs_group).as_deref(),
+            Some("Containers of pod web set different fsGroup")
+        );
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
     },
     error::Result,
     reconciler::Context,
+    resources::pod::PodGroup,
 };
 use k8s_openapi::{
     api::core::v1::{Service, ServicePort, ServiceSpec},
@@ -17,13 +18,14 @@ use kube::{
 use std::collections::BTreeMap;
 use tracing::{debug, info};
 
-/// reconcile attempts to create ClusterIP and NodePort services for a deployment as required
+/// reconcile attempts to create ClusterIP and NodePort services for a pod as required
+/// every exposed container gets a ClusterIP service named after its hostname
 /// if the service already exists it returns Ok without attempting to mutate the object
 pub async fn reconcile(
     class: &ChallengeInstanceClass,
     instance: &ChallengeInstance,
     _challenge: &Challenge,
-    container: &ContainerSpec,
+    pod: &PodGroup<'_>,
     namespace: &str,
     ctx: &Context,
 ) -> Result<Vec<ServiceEndpoint>> {
@@ -31,14 +33,21 @@ pub async fn reconcile(
 
     let mut endpoints = vec![];
 
-    // create ClusterIP service
-    if !container.ports.is_empty() {
-        let service_name = container.hostname.to_string();
+    // auxiliary containers are never exposed
+    let exposed = pod
+        .containers
+        .iter()
+        .filter(|c| !c.is_auxiliary())
+        .collect::<Vec<_>>();
+
+    // create a ClusterIP service per container so its hostname resolves inside the pod group
+    for container in exposed.iter().filter(|c| !c.ports.is_empty()) {
+        let service_name = container.hostname.clone();
         let svc = make_svc(
             &service_name,
             "ClusterIP",
             namespace,
-            &container.hostname,
+            pod.name,
             &container.ports,
             instance.controller_owner_ref(&()).unwrap(),
         );
@@ -52,30 +61,58 @@ pub async fn reconcile(
         };
     }
 
+    // headless services resolve to the pod address, e.g. for clustered software
+    if class
+        .spec
+        .network
+        .as_ref()
+        .is_some_and(|n| n.additional_headless_service)
+    {
+        for container in &exposed {
+            let svc = make_headless_svc(
+                container,
+                namespace,
+                instance.controller_owner_ref(&()).unwrap(),
+            );
+            let service_name = svc.metadata.name.clone().unwrap_or_default();
+
+            match api.create(&PostParams::default(), &svc).await {
+                Ok(_) => info!("Created service {} in {}", service_name, namespace),
+                Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                    debug!("Service {} already exists", service_name)
+                }
+                Err(e) => return Err(e.into()),
+            };
+        }
+    }
+
     // if any nodeport ports exist, create a node port service
-    let node_ports = container
-        .ports
+    // endpoints keep the hostname of the container exposing the port
+    let node_ports = exposed
         .iter()
-        .filter(|p| p.r#type == PortType::PublicPort)
-        .cloned()
+        .flat_map(|c| c.ports.iter().map(move |p| (&c.hostname, p)))
+        .filter(|(_, p)| p.r#type == PortType::PublicPort)
         .collect::<Vec<_>>();
     if !node_ports.is_empty() {
-        let service_name = format!("{}-node-port", container.hostname);
+        let service_name = format!("{}-node-port", pod.name);
         let svc = make_svc(
             &service_name,
             "NodePort",
             namespace,
-            &container.hostname,
-            &node_ports,
+            pod.name,
+            &node_ports
+                .iter()
+                .map(|(_, p)| (*p).clone())
+                .collect::<Vec<_>>(),
             instance.controller_owner_ref(&()).unwrap(),
         );
         let svc = api.create(&PostParams::default(), &svc).await;
 
         if let Ok(svc) = svc {
-            for port in &node_ports {
+            for (hostname, port) in &node_ports {
                 endpoints.push(ServiceEndpoint {
                     name: (port.name.to_owned())
-                        .unwrap_or(format!("{}:{}", container.hostname, port.port))
+                        .unwrap_or(format!("{}:{}", hostname, port.port))
                         .to_owned(),
                     hostname: class.spec.gateway.domain.clone(),
                     port: svc
@@ -108,6 +145,22 @@ pub async fn reconcile(
     Ok(endpoints)
 }
 
+/// Headless service of a container, named after its hostname
+fn make_headless_svc(container: &ContainerSpec, namespace: &str, oref: OwnerReference) -> Service {
+    let mut svc = make_svc(
+        &format!("{}-headless", container.hostname),
+        "ClusterIP",
+        namespace,
+        container.pod_name(),
+        &container.ports,
+        oref,
+    );
+    if let Some(ref mut spec) = svc.spec {
+        spec.cluster_ip = Some("None".to_string());
+    }
+    svc
+}
+
 fn make_svc(
     name: &str,
     service_type: &str,
@@ -162,3 +215,32 @@ fn make_svc(
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_headless_service() {
+        let challenge = crate::test_fixtures::challenge(json!({
+            "containers": [{
+                "hostname": "node-1",
+                "image": "cluster",
+                "pod": "nodes",
+                "ports": [{ "port": 7000, "protocol": "tcp" }]
+            }]
+        }));
+        let container = &challenge.spec.containers[0];
+
+        let svc = make_headless_svc(container, "instance-ns", OwnerReference::default());
+        assert_eq!(svc.metadata.name.as_deref(), Some("node-1-headless"));
+        let spec = svc.spec.unwrap();
+        assert_eq!(spec.cluster_ip.as_deref(), Some("None"));
+        assert_eq!(
+            spec.selector.unwrap()["berg.norelect.ch/container"],
+            "nodes"
+        );
+        assert_eq!(spec.ports.unwrap()[0].protocol.as_deref(), Some("TCP"));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    crds::{ChallengeInstance, ChallengeInstanceClass, ContainerSpec, StorageMedium, VolumeSpec},
+    error::Result,
+    reconciler::Context,
+};
+use k8s_openapi::{
+    api::core::v1::{
+        EmptyDirVolumeSource, PersistentVolumeClaim, PersistentVolumeClaimSpec,
+        PersistentVolumeClaimVolumeSource, Volume, VolumeMount, VolumeResourceRequirements,
+    },
+    apimachinery::pkg::api::resource::Quantity,
+};
+use kube::{
+    api::{Api, DeleteParams, ListParams, ObjectMeta, PostParams},
+    Resource,
+};
+use std::collections::BTreeMap;
+use tracing::{debug, info};
+
+/// Name of the claim backing a persistent volume of a container
+/// Claims are named per pod, so containers of a pod share volumes of the same name
+pub fn claim_name(container: &ContainerSpec, volume: &VolumeSpec) -> String {
+    format!("{}-{}", container.pod_name(), volume.name)
+}
+
+/// Build the pod volume and the container mount of a volume
+pub fn build_volume_mount(container: &ContainerSpec, spec: &VolumeSpec) -> (Volume, VolumeMount) {
+    let volume_name = format!("volume-{}", spec.name);
+
+    let volume = match (&spec.persistent, &spec.empty_dir) {
+        (Some(_), _) => Volume {
+            name: volume_name.clone(),
+            persistent_volume_claim: Some(PersistentVolumeClaimVolumeSource {
+                claim_name: claim_name(container, spec),
+                read_only: None,
+            }),
+            ..Default::default()
+        },
+        (None, empty_dir) => Volume {
+            name: volume_name.clone(),
+            empty_dir: Some(EmptyDirVolumeSource {
+                medium: empty_dir.as_ref().and_then(|e| e.medium.as_ref()).map(
+                    |medium| match medium {
+                        StorageMedium::Memory => "Memory".to_string(),
+                    },
+                ),
+                size_limit: empty_dir
+                    .as_ref()
+                    .and_then(|e| e.size_limit.clone())
+                    .map(Quantity),
+            }),
+            ..Default::default()
+        },
+    };
+
+    let mount = VolumeMount {
+        name: volume_name,
+        mount_path: spec.mount_path.clone(),
+        ..Default::default()
+    };
+
+    (volume, mount)
+}
+
+/// Build the claim of a persistent volume, owned by the instance
+fn build_claim(
+    instance: &ChallengeInstance,
+    container: &ContainerSpec,
+    spec: &VolumeSpec,
+    size: &str,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+) -> PersistentVolumeClaim {
+    PersistentVolumeClaim {
+        metadata: ObjectMeta {
+            name: Some(claim_name(container, spec)),
+            namespace: Some(namespace.to_string()),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            labels: Some(BTreeMap::from([(
+                "berg.norelect.ch/container".to_string(),
+                container.pod_name().to_string(),
+            )])),
+            ..Default::default()
+        },
+        spec: Some(PersistentVolumeClaimSpec {
+            access_modes: Some(vec!["ReadWriteOnce".to_string()]),
+            storage_class_name: class
+                .spec
+                .storage
+                .as_ref()
+                .and_then(|s| s.storage_class_name.clone()),
+            resources: Some(VolumeResourceRequirements {
+                requests: Some(BTreeMap::from([(
+                    "storage".to_string(),
+                    Quantity(size.to_string()),
+                )])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// reconcile creates the claims of the persistent volumes of a container
+/// existing claims are left untouched, so their data survives pod restarts
+pub async fn reconcile(
+    instance: &ChallengeInstance,
+    container: &ContainerSpec,
+    namespace: &str,
+    class: &ChallengeInstanceClass,
+    ctx: &Context,
+) -> Result<()> {
+    let api: Api<PersistentVolumeClaim> = Api::namespaced(ctx.client.clone(), namespace);
+
+    for spec in &container.volumes {
+        let Some(ref persistent) = spec.persistent else {
+            continue;
+        };
+
+        let claim = build_claim(
+            instance,
+            container,
+            spec,
+            &persistent.size,
+            namespace,
+            class,
+        );
+        match api.create(&PostParams::default(), &claim).await {
+            Ok(_) => info!(
+                "Created PersistentVolumeClaim {} in {}",
+                claim_name(container, spec),
+                namespace
+            ),
+            Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                debug!(
+                    "PersistentVolumeClaim {} already exists",
+                    claim_name(container, spec)
+                )
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete every claim in the namespace of an instance
+/// Returns whether claims are still being deleted
+pub async fn delete_claims(namespace: &str, ctx: &Context) -> Result<bool> {
+    let api: Api<PersistentVolumeClaim> = Api::namespaced(ctx.client.clone(), namespace);
+
+    let claims = match api.list(&ListParams::default()).await {
+        Ok(claims) => claims,
+        Err(kube::Error::Api(ae)) if ae.code == 404 => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut deleting = false;
+    for claim in claims {
+        deleting = true;
+        if claim.metadata.deletion_timestamp.is_none() {
+            let name = claim.metadata.name.unwrap_or_default();
+            match api.delete(&name, &DeleteParams::default()).await {
+                Ok(_) => info!("Deleted PersistentVolumeClaim {} in {}", name, namespace),
+                Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    Ok(deleting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn container(volumes: serde_json::Value) -> ContainerSpec {
+        serde_json::from_value(json!({
+            "hostname": "web",
+            "image": "nginx",
+            "volumes": volumes
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_memory_empty_dir() {
+        let container = container(json!([{
+            "name": "shm",
+            "mountPath": "/dev/shm",
+            "emptyDir": { "medium": "Memory", "sizeLimit": "256Mi" }
+        }]));
+
+        let (volume, mount) = build_volume_mount(&container, &container.volumes[0]);
+        let empty_dir = volume.empty_dir.unwrap();
+        assert_eq!(empty_dir.medium.as_deref(), Some("Memory"));
+        assert_eq!(empty_dir.size_limit, Some(Quantity("256Mi".to_string())));
+        assert_eq!(mount.mount_path, "/dev/shm");
+        assert_eq!(mount.name, volume.name);
+    }
+
+    #[test]
+    fn test_persistent_volume() {
+        let container = container(json!([{
+            "name": "data",
+            "mountPath": "/var/lib/data",
+            "persistent": { "size": "1Gi" }
+        }]));
+
+        let (volume, _) = build_volume_mount(&container, &container.volumes[0]);
+        assert!(volume.empty_dir.is_none());
+        assert_eq!(
+            volume.persistent_volume_claim.unwrap().claim_name,
+            "web-data"
+        );
+    }
+}
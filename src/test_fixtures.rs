@@ -0,0 +1,68 @@
+//! Objects shared by the unit tests, the given spec fields are merged into a minimal valid spec
+
+use crate::crds::{Challenge, ChallengeInstance, ChallengeInstanceClass};
+use serde_json::{json, Value};
+
+/// Merge the fields of `extra` into the object `base`
+fn merge(mut base: Value, extra: Value) -> Value {
+    if let (Some(base), Value::Object(extra)) = (base.as_object_mut(), extra) {
+        base.extend(extra);
+    }
+    base
+}
+
+pub fn instance() -> ChallengeInstance {
+    serde_json::from_value(json!({
+        "apiVersion": "berg.norelect.ch/v1",
+        "kind": "ChallengeInstance",
+        "metadata": { "name": "test", "uid": "8f5c1f8e-0000-0000-0000-000000000000" },
+        "spec": {
+            "challengeRef": { "name": "test" },
+            "ownerId": "owner",
+            "flag": "flag{test}"
+        }
+    }))
+    .unwrap()
+}
+
+/// Challenge with the given spec fields, e.g. `json!({ "containers": [...] })`
+pub fn challenge(spec: Value) -> Challenge {
+    serde_json::from_value(json!({
+        "apiVersion": "berg.norelect.ch/v1",
+        "kind": "Challenge",
+        "metadata": { "name": "test" },
+        "spec": merge(
+            json!({
+                "author": "test",
+                "description": "test",
+                "flag": "flag{test}",
+                "difficulty": "easy",
+                "categories": []
+            }),
+            spec
+        )
+    }))
+    .unwrap()
+}
+
+/// Instance class with the given spec fields next to the gateway
+pub fn class(spec: Value) -> ChallengeInstanceClass {
+    serde_json::from_value(json!({
+        "apiVersion": "berg.norelect.ch/v1",
+        "kind": "ChallengeInstanceClass",
+        "metadata": { "name": "default" },
+        "spec": merge(
+            json!({
+                "gateway": {
+                    "name": "gateway",
+                    "namespace": "berg",
+                    "httpListenerName": "https",
+                    "tlsListenerName": "tls",
+                    "domain": "chall.example.com"
+                }
+            }),
+            spec
+        )
+    }))
+    .unwrap()
+}
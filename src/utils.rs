@@ -1,23 +1,187 @@
 /// Utility functions for the berg-controller
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::sync::LazyLock;
+
+/// Pattern of a non-negative Kubernetes quantity, e.g. "10M" or "1Gi"
+pub const QUANTITY_PATTERN: &str =
+    r"^(([0-9]+(\.[0-9]*)?)|(\.[0-9]+))(([KMGTPE]i)|[numkMGTPE]|([eE][+-]?[0-9]+))?$";
+
+static QUANTITY_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(QUANTITY_PATTERN).unwrap());
+
+/// Maximum length of a namespace name
+const MAX_NAMESPACE_LEN: usize = 63;
+
+/// Length of the hash suffix of hashed namespace names
+const NAMESPACE_HASH_LEN: usize = 16;
+
+/// Maximum length of a DNS label
+const MAX_LABEL_LEN: usize = 63;
+
+/// Length of the hash suffix of shortened labels
+const LABEL_HASH_LEN: usize = 8;
+
+/// Hex encoded SHA-256 of a string
+pub fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
 /// Generate a namespace name from an owner ID
 pub fn generate_namespace_name(namespace_prefix: &str, challenge_name: &str, id: &str) -> String {
-    let max_chall_name_len = 63 - (namespace_prefix.len() + id.len() + 2);
-    let challenge_name = if challenge_name.len() > max_chall_name_len {
-        &challenge_name[..max_chall_name_len]
-    } else {
-        challenge_name
-    };
-    format!("{}-{}-{}", namespace_prefix, challenge_name, id)
+    let max_chall_name_len =
+        MAX_NAMESPACE_LEN.saturating_sub(namespace_prefix.len() + id.len() + 2);
+    format!(
+        "{}-{}-{}",
+        namespace_prefix,
+        truncate_label(challenge_name, max_chall_name_len),
+        id
+    )
+}
+
+/// Generate a namespace name unique to an instance
+/// The hash covers the instance ID, so concurrent instances of one owner never share a namespace
+pub fn generate_hashed_namespace_name(
+    namespace_prefix: &str,
+    challenge_name: &str,
+    owner_id: &str,
+    instance_id: &str,
+) -> String {
+    let hash = sha256_hex(&format!(
+        "{}\0{}\0{}",
+        challenge_name, owner_id, instance_id
+    ));
+    generate_namespace_name(
+        namespace_prefix,
+        challenge_name,
+        &hash[..NAMESPACE_HASH_LEN],
+    )
+}
+
+/// Shorten a name to at most max_len characters while keeping it a valid part of a DNS label
+/// Dots of resource names are replaced and no dash is left dangling at the cut
+fn truncate_label(name: &str, max_len: usize) -> String {
+    let truncated: String = name
+        .chars()
+        .map(|c| if c == '.' { '-' } else { c })
+        .take(max_len)
+        .collect();
+    truncated.trim_end_matches('-').to_string()
+}
+
+/// Shorten a composed name to a DNS label, keeping it unique with a hash of the full name
+pub fn shorten_label(name: &str) -> String {
+    if name.len() <= MAX_LABEL_LEN {
+        return name.to_string();
+    }
+    let hash = sha256_hex(name);
+    format!(
+        "{}-{}",
+        truncate_label(name, MAX_LABEL_LEN - LABEL_HASH_LEN - 1),
+        &hash[..LABEL_HASH_LEN]
+    )
+}
+
+/// Check if a string is a valid RFC 1123 DNS label
+pub fn is_dns_label(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_LABEL_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Check if a string is a valid non-negative Kubernetes quantity
+pub fn is_quantity(value: &str) -> bool {
+    QUANTITY_REGEX.is_match(value)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_quantity() {
+        for valid in ["10M", "1Gi", "500k", "1.5G", "100", "1e6"] {
+            assert!(is_quantity(valid), "{}", valid);
+        }
+        for invalid in ["", "10 M", "10MB", "-1M", "fast", "1Mi/s"] {
+            assert!(!is_quantity(invalid), "{}", invalid);
+        }
+    }
+
     #[test]
     fn test_namespace_name_generation() {
         let owner_id = "a1b2c3d4-e5f6-7890-abcd-ef1234567890";
         let expected = "ci-nginx-a1b2c3d4-e5f6-7890-abcd-ef1234567890";
         assert_eq!(generate_namespace_name("ci", "nginx", owner_id), expected);
     }
+
+    #[test]
+    fn test_namespace_name_truncation() {
+        let owner_id = "a1b2c3d4-e5f6-7890-abcd-ef1234567890";
+        let name = generate_namespace_name("ci", "a-very-long-challenges-name", owner_id);
+        // cut right after a dash, which must not end up next to the separator
+        assert_eq!(
+            name,
+            "ci-a-very-long-challenges-a1b2c3d4-e5f6-7890-abcd-ef1234567890"
+        );
+        assert!(is_dns_label(&name));
+
+        let name = generate_namespace_name("ci", "web.app", "0123456789abcdef");
+        assert_eq!(name, "ci-web-app-0123456789abcdef");
+    }
+
+    #[test]
+    fn test_hashed_namespace_name() {
+        let owner_id = "a1b2c3d4-e5f6-7890-abcd-ef1234567890";
+        let first = generate_hashed_namespace_name("ci", "nginx", owner_id, "instance-1");
+        let second = generate_hashed_namespace_name("ci", "nginx", owner_id, "instance-2");
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            generate_hashed_namespace_name("ci", "nginx", owner_id, "instance-1")
+        );
+        assert!(first.starts_with("ci-nginx-"));
+        assert!(is_dns_label(&first));
+
+        let long = generate_hashed_namespace_name("ci", &"x".repeat(100), owner_id, "instance-1");
+        assert_eq!(long.len(), 63);
+        assert!(is_dns_label(&long));
+    }
+
+    #[test]
+    fn test_is_dns_label() {
+        assert!(is_dns_label("root"));
+        assert!(is_dns_label("stage-2"));
+        assert!(!is_dns_label("Root"));
+        assert!(!is_dns_label("-root"));
+        assert!(!is_dns_label("stage_2"));
+        assert!(!is_dns_label(""));
+    }
+
+    #[test]
+    fn test_shorten_label() {
+        assert_eq!(shorten_label("web-volume-data"), "web-volume-data");
+
+        let long = format!("{}-volume-data", "a".repeat(63));
+        let shortened = shorten_label(&long);
+        assert!(is_dns_label(&shortened));
+        assert_ne!(
+            shortened,
+            shorten_label(&format!("{}-volume-logs", "a".repeat(63)))
+        );
+    }
 }